@@ -0,0 +1,151 @@
+// The REPL's own commands, the lines that start with a `:`. They look at the
+// session or at how something is compiled, they aren't bc code.
+
+use crate::compiler::{Compiler, Param};
+use crate::error::Error;
+use crate::parser::{AstNode, NodeKind, Parser};
+use crate::tokenizer::{tokens_from_text, TokenKind};
+use crate::{report, Session};
+use std::time::Instant;
+
+const HELP: &str = "\
+:vars         list the variables and arrays
+:funcs        list the functions
+:tokens EXPR  show the tokens of EXPR
+:ast EXPR     show the syntax tree of EXPR
+:ops EXPR     show the instructions EXPR compiles to, without running it
+:time EXPR    run EXPR and show how long it took
+:save FILE    save the session to FILE, the same as --save
+:load FILE    get a saved session back, or run any bc file
+:reset        forget everything, like a new bc
+:help         show this";
+
+impl Session {
+    pub fn command(&mut self, line: &str) {
+        let (name, argument) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let argument = argument.trim();
+
+        match name {
+            ":vars" => self.list_vars(),
+            ":funcs" => self.list_functions(),
+            ":tokens" => show_tokens(argument),
+            ":ast" => show_ast(argument),
+            ":ops" => self.show_ops(argument),
+            ":time" => {
+                let start = Instant::now();
+                self.run_and_report(argument, None, 1);
+                println!("Took {:?}", start.elapsed());
+            }
+            ":save" | ":load" if argument.is_empty() => {
+                eprintln!("`{}` needs a file name", name);
+            }
+            ":save" => {
+                self.save(argument);
+            }
+            ":load" => {
+                self.load(argument);
+            }
+            ":reset" => *self = Session::new(self.extensions, self.mathlib),
+            ":help" => println!("{}", HELP),
+            _ => eprintln!("Unknown command `{}`, `:help` lists them", name),
+        }
+    }
+
+    fn list_vars(&self) {
+        let mut names: Vec<&String> = self.env.vars.keys().collect();
+        names.sort();
+        for name in names {
+            println!("{} = {}", name, self.env.vars[name]);
+        }
+
+        // Elements that were never set are zero, they'd only be noise.
+        let mut names: Vec<&String> = self.env.arrays.keys().collect();
+        names.sort();
+        for name in names {
+            for (idx, val) in &self.env.arrays[name] {
+                if !val.is_zero() {
+                    println!("{}[{}] = {}", name, idx, val);
+                }
+            }
+        }
+    }
+
+    fn list_functions(&self) {
+        let mut names: Vec<&String> = self.functions.keys().collect();
+        names.sort();
+        for name in names {
+            let function = &self.functions[name];
+            let mut signature = format!("{}({})", name, param_list(&function.params));
+            if !function.autos.is_empty() {
+                signature.push_str(&format!(" auto {}", param_list(&function.autos)));
+            }
+            if function.text.is_none() {
+                signature.push_str("  (math library)");
+            }
+            println!("{}", signature);
+        }
+    }
+
+    // Compiles against a copy of the functions, so a `define` here doesn't define anything.
+    fn show_ops(&self, source: &str) {
+        let (statements, errors) = parse(source);
+        if !errors.is_empty() {
+            return report(source, None, &errors);
+        }
+
+        let mut functions = self.functions.clone();
+        let ops = match Compiler::new(&mut functions, source).compile(&statements) {
+            Ok(ops) => ops,
+            Err(error) => return report(source, None, &[error]),
+        };
+        for (idx, op) in ops.iter().enumerate() {
+            println!("{:4}  {:?}", idx, op);
+        }
+
+        for statement in &statements {
+            if let NodeKind::Define(name, ..) = statement.kind {
+                println!("{}:", name);
+                for (idx, op) in functions[name].ops.iter().enumerate() {
+                    println!("{:4}  {:?}", idx, op);
+                }
+            }
+        }
+    }
+}
+
+fn show_tokens(source: &str) {
+    let (tokens, errors) = tokens_from_text(source);
+    for token in tokens.iter().filter(|token| token.kind != TokenKind::EOF) {
+        println!("{:?}", token.kind);
+    }
+    report(source, None, &errors);
+}
+
+fn show_ast(source: &str) {
+    let (statements, errors) = parse(source);
+    for statement in statements {
+        println!("{}", statement);
+    }
+    report(source, None, &errors);
+}
+
+fn parse(source: &str) -> (Vec<AstNode<'_>>, Vec<Error>) {
+    let (tokens, mut errors) = tokens_from_text(source);
+    let (statements, parse_errors) = Parser::new(tokens).parse();
+    errors.extend(parse_errors);
+    (statements, errors)
+}
+
+fn param_list(params: &[Param]) -> String {
+    let names: Vec<String> = params
+        .iter()
+        .map(|param| {
+            if param.is_array {
+                format!("{}[]", param.name)
+            } else {
+                param.name.clone()
+            }
+        })
+        .collect();
+    names.join(", ")
+}
@@ -1,8 +1,12 @@
-use crate::parser::AstNode;
-use crate::tokenizer::Token;
+use crate::error::Error;
+use crate::mathlib::MathFn;
+use crate::number::Number;
+use crate::parser::{self, is_assignment_op, AstNode, NodeKind};
+use crate::tokenizer::TokenKind;
+use std::collections::HashMap;
 
-#[derive(Debug)]
-pub enum Instruction<'source> {
+#[derive(Debug, Clone)]
+pub enum Instruction {
     Add,
     Sub,
     Negate,
@@ -10,92 +14,553 @@ pub enum Instruction<'source> {
     Div,
     Mod,
     Pow,
-    GetVal(&'source str),
-    Assign(&'source str),
-    PushConstant(f64),
-    // TODO PopAndAssign(&'source str), // For function arguments.
-    CallFn(&'source str),
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Not,
+    GetVal(String),
+    Assign(String),
+    GetElement(String),    // Pops the index.
+    AssignElement(String), // Pops the index, then the value.
+    PushArray(String),     // A copy of the whole array, for passing it to a function.
+    // Add or subtract one, then push the new value, or the old one if the bool
+    // is set (postfix `x++`). The element ones pop the index.
+    Increment(String, bool),
+    Decrement(String, bool),
+    IncrementElement(String, bool),
+    DecrementElement(String, bool),
+    PushConstant(Number),
+    PushNumber(String), // A number from the source, read in the `ibase` of the moment.
+    CallFn(String, usize), // Function name and the number of arguments on the stack.
+    Math(MathFn),          // The body of a math library function, see `mathlib`.
+    Return,
+    Halt,  // Stops bc, even from inside a function.
+    Print, // Pops the value and prints it, for expression statements.
+    Pop,   // Pops the value and throws it away, for assignments.
+    Duplicate,
+    Swap,  // Swaps the two values on top.
+    Write, // Pops the value and prints it without a newline, for `print`.
+    WriteString(String),
+    Jump(usize),       // Index of the instruction to go on from.
+    JumpIfZero(usize), // Pops the condition, jumps if it's zero.
 }
 
-pub struct Compiler<'source> {
-    operations: Vec<Instruction<'source>>,
+// Functions outlive the source text they were defined in, so everything
+// in here is owned.
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub params: Vec<Param>,
+    pub autos: Vec<Param>, // Locals, they start as zero or empty in every call.
+    pub ops: Vec<Instruction>,
+    pub text: Option<String>, // The definition as it was written, the math library has none.
 }
 
-impl<'source> Compiler<'source> {
-    pub fn new() -> Self {
-        Self { operations: vec![] }
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub name: String,
+    pub is_array: bool,
+}
+
+// The functions that are always there, and how many arguments they take.
+pub const BUILTIN_FUNCTIONS: [(&str, usize); 5] =
+    [("sin", 1), ("cos", 1), ("sqrt", 1), ("scale", 1), ("length", 1)];
+
+pub fn builtin_arity(fn_name: &str) -> Option<usize> {
+    BUILTIN_FUNCTIONS
+        .iter()
+        .find(|(name, _)| *name == fn_name)
+        .map(|(_, arity)| *arity)
+}
+
+pub fn arity_error(fn_name: &str, expected: usize, actual: usize) -> String {
+    format!(
+        "Function `{}` takes {} argument{} but {} {} given!",
+        fn_name,
+        expected,
+        if expected == 1 { "" } else { "s" },
+        actual,
+        if actual == 1 { "was" } else { "were" }
+    )
+}
+
+// The jumps of `break`s and `continue`s of a loop, to be patched when we
+// know where they go.
+struct Loop {
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+pub struct Compiler<'fns> {
+    operations: Vec<Instruction>,
+    functions: &'fns mut HashMap<String, Function>,
+    source: &'fns str, // What the statements were parsed from.
+    loops: Vec<Loop>,
+}
+
+impl<'fns> Compiler<'fns> {
+    pub fn new(functions: &'fns mut HashMap<String, Function>, source: &'fns str) -> Self {
+        Self {
+            operations: vec![],
+            functions,
+            source,
+            loops: vec![],
+        }
     }
 
-    pub fn compile(mut self, ast_root: AstNode<'source>) -> Vec<Instruction<'source>> {
-        self.compile_ast_node(&ast_root, false);
-        self.operations
+    // Jump targets are indexes into the returned instructions, so all the
+    // statements that run together have to be compiled together.
+    pub fn compile(mut self, statements: &[AstNode]) -> Result<Vec<Instruction>, Error> {
+        for statement in statements {
+            self.compile_statement(statement)?;
+        }
+        Ok(self.operations)
     }
 
-    fn compile_ast_node(&mut self, node: &AstNode<'source>, is_equals: bool) {
-        match node {
-            AstNode::Ident(ident) => {
-                if is_equals {
-                    self.operations.push(Instruction::Assign(ident));
+    fn compile_statement(&mut self, node: &AstNode) -> Result<(), Error> {
+        match &node.kind {
+            NodeKind::Define(name, params, autos, body) => {
+                // Functions are defined as soon as they are compiled, like bc does
+                // it when it reads them. The body gets its own instruction list.
+                let to_params = |params: &[parser::Param]| -> Vec<Param> {
+                    params
+                        .iter()
+                        .map(|param| Param {
+                            name: param.name.to_string(),
+                            is_array: param.is_array,
+                        })
+                        .collect()
+                };
+                let params = to_params(params);
+                let autos = to_params(autos);
+
+                // Define it with an empty body first, so recursive calls can be checked too.
+                let previous = self.functions.insert(
+                    name.to_string(),
+                    Function {
+                        params: params.clone(),
+                        autos: vec![],
+                        ops: vec![],
+                        text: None,
+                    },
+                );
+
+                let mut compiler = Compiler::new(self.functions, self.source);
+                let compiled = body
+                    .iter()
+                    .try_for_each(|statement| compiler.compile_statement(statement));
+                let mut ops = compiler.operations;
+
+                if let Err(error) = compiled {
+                    match previous {
+                        Some(function) => self.functions.insert(name.to_string(), function),
+                        None => self.functions.remove(*name),
+                    };
+                    return Err(error);
+                }
+
+                // Falling off the end of a function returns zero.
+                ops.push(Instruction::PushConstant(Number::zero()));
+                ops.push(Instruction::Return);
+
+                let text = Some(self.source[node.span.start..node.span.end].to_string());
+                let function = Function {
+                    params,
+                    autos,
+                    ops,
+                    text,
+                };
+                self.functions.insert(name.to_string(), function);
+            }
+            NodeKind::Return(value) => {
+                match value {
+                    Some(value) => self.compile_ast_node(value)?,
+                    None => self.operations.push(Instruction::PushConstant(Number::zero())),
+                }
+                self.operations.push(Instruction::Return);
+            }
+            NodeKind::Block(statements) => {
+                for statement in statements {
+                    self.compile_statement(statement)?;
+                }
+            }
+            NodeKind::If(condition, then, otherwise) => {
+                self.compile_ast_node(condition)?;
+                let jump_over_then = self.push_jump(Instruction::JumpIfZero(0));
+                self.compile_statement(then)?;
+
+                match otherwise {
+                    Some(otherwise) => {
+                        let jump_over_else = self.push_jump(Instruction::Jump(0));
+                        self.patch_jump(jump_over_then, self.operations.len());
+                        self.compile_statement(otherwise)?;
+                        self.patch_jump(jump_over_else, self.operations.len());
+                    }
+                    None => self.patch_jump(jump_over_then, self.operations.len()),
+                }
+            }
+            NodeKind::While(condition, body) => {
+                let start = self.operations.len();
+                self.compile_ast_node(condition)?;
+                let exit = self.push_jump(Instruction::JumpIfZero(0));
+
+                let lp = self.compile_loop_body(body)?;
+                self.operations.push(Instruction::Jump(start));
+
+                self.patch_loop(lp, start, self.operations.len());
+                self.patch_jump(exit, self.operations.len());
+            }
+            NodeKind::For(init, condition, step, body) => {
+                if let Some(init) = init {
+                    self.compile_ast_node(init)?;
+                    self.operations.push(Instruction::Pop);
+                }
+
+                let start = self.operations.len();
+                let exit = match condition {
+                    Some(condition) => {
+                        self.compile_ast_node(condition)?;
+                        Some(self.push_jump(Instruction::JumpIfZero(0)))
+                    }
+                    None => None,
+                };
+
+                let lp = self.compile_loop_body(body)?;
+
+                let step_start = self.operations.len();
+                if let Some(step) = step {
+                    self.compile_ast_node(step)?;
+                    self.operations.push(Instruction::Pop);
+                }
+                self.operations.push(Instruction::Jump(start));
+
+                self.patch_loop(lp, step_start, self.operations.len());
+                if let Some(exit) = exit {
+                    self.patch_jump(exit, self.operations.len());
+                }
+            }
+            NodeKind::Break | NodeKind::Continue => {
+                let jump = self.push_jump(Instruction::Jump(0));
+                let is_break = matches!(node.kind, NodeKind::Break);
+                match self.loops.last_mut() {
+                    Some(lp) if is_break => lp.breaks.push(jump),
+                    Some(lp) => lp.continues.push(jump),
+                    None => {
+                        let keyword = if is_break { "break" } else { "continue" };
+                        let msg = format!("`{}` outside of a loop!", keyword);
+                        return Err(Error::Compile(msg, node.span));
+                    }
+                }
+            }
+            NodeKind::Halt => self.operations.push(Instruction::Halt),
+            NodeKind::Str(text) => {
+                self.operations.push(Instruction::WriteString(text.to_string()));
+            }
+            NodeKind::Print(items) => {
+                for item in items {
+                    match &item.kind {
+                        NodeKind::Str(text) => {
+                            self.operations.push(Instruction::WriteString(unescape(text)));
+                        }
+                        _ => {
+                            self.compile_ast_node(item)?;
+                            self.operations.push(Instruction::Write);
+                        }
+                    }
+                }
+            }
+            _ => {
+                self.compile_ast_node(node)?;
+                // bc prints the value of every expression statement, except
+                // assignments. `(x = 5)` isn't one, it's in parentheses.
+                if is_assignment(node) {
+                    self.operations.push(Instruction::Pop);
                 } else {
-                    self.operations.push(Instruction::GetVal(ident));
+                    self.operations.push(Instruction::Print);
                 }
             }
-            AstNode::Number(number) => {
-                self.operations.push(Instruction::PushConstant(*number));
+        }
+        Ok(())
+    }
+
+    fn compile_ast_node(&mut self, node: &AstNode) -> Result<(), Error> {
+        match &node.kind {
+            NodeKind::Ident(ident) => {
+                self.operations.push(Instruction::GetVal(ident.to_string()));
             }
-            AstNode::Op(op_token, children_nodes) => {
-                let mut equals = false;
-                let mut op_equals = false;
-                let child_count = children_nodes.len();
-                let is_negation = *op_token == Token::Minus && child_count == 1;
-                if *op_token == Token::Equals {
-                    equals = true;
+            NodeKind::Paren(inner) => self.compile_ast_node(inner)?,
+            NodeKind::Element(name, index) => {
+                self.compile_ast_node(index)?;
+                self.operations.push(Instruction::GetElement(name.to_string()));
+            }
+            NodeKind::Array(name) => {
+                let msg = format!("`{}[]` can only be passed to a function!", name);
+                return Err(Error::Compile(msg, node.span));
+            }
+            NodeKind::Number(number_str) => {
+                // Without the backslash-newlines a long number can be broken with.
+                let digits = number_str.replace("\\\n", "");
+                self.operations.push(Instruction::PushNumber(digits));
+            }
+            NodeKind::Op(TokenKind::Equals, children_nodes) => {
+                // Only the value is evaluated, the left side is where it goes.
+                self.compile_ast_node(&children_nodes[1])?;
+                match &children_nodes[0].kind {
+                    NodeKind::Ident(ident) => {
+                        self.operations.push(Instruction::Assign(ident.to_string()));
+                    }
+                    NodeKind::Element(name, index) => {
+                        self.compile_ast_node(index)?;
+                        self.operations.push(Instruction::AssignElement(name.to_string()));
+                    }
+                    _ => unreachable!("assignment to {}", children_nodes[0]),
+                }
+            }
+            NodeKind::Op(op_token, children_nodes) if is_assignment_op(*op_token) => {
+                // x += y is x = x + y, but the index of an element is only evaluated once.
+                match &children_nodes[0].kind {
+                    NodeKind::Ident(ident) => {
+                        self.compile_ast_node(&children_nodes[1])?;
+                        self.operations.push(Instruction::GetVal(ident.to_string()));
+                        self.push_op(*op_token, node)?;
+                        self.operations.push(Instruction::Assign(ident.to_string()));
+                    }
+                    NodeKind::Element(name, index) => {
+                        self.compile_ast_node(index)?;
+                        self.operations.push(Instruction::Duplicate);
+                        self.operations.push(Instruction::GetElement(name.to_string()));
+                        self.compile_ast_node(&children_nodes[1])?;
+                        self.operations.push(Instruction::Swap); // The element is the left operand.
+                        self.push_op(*op_token, node)?;
+                        self.operations.push(Instruction::Swap); // The index goes on top.
+                        self.operations.push(Instruction::AssignElement(name.to_string()));
+                    }
+                    _ => unreachable!("assignment to {}", children_nodes[0]),
                 }
+            }
+            NodeKind::Op(op @ (TokenKind::Increment | TokenKind::Decrement), operand) => {
+                self.compile_step(*op, &operand[0], false)?;
+            }
+            NodeKind::Postfix(op, operand) => {
+                self.compile_step(*op, operand, true)?;
+            }
+            NodeKind::Op(TokenKind::FnCall(fn_name), args) => {
+                // Functions that aren't defined yet can still be called from
+                // function bodies, the vm checks those when they are called.
+                let array_params: Option<Vec<bool>> = match self.functions.get(*fn_name) {
+                    Some(function) => Some(function.params.iter().map(|p| p.is_array).collect()),
+                    None => builtin_arity(fn_name).map(|arity| vec![false; arity]),
+                };
+                if let Some(array_params) = &array_params {
+                    if array_params.len() != args.len() {
+                        let msg = arity_error(fn_name, array_params.len(), args.len());
+                        return Err(Error::Compile(msg, node.span));
+                    }
+                }
+
+                // Don't forget that the arguments get pushed to stack in reverse.
+                for (idx, arg) in args.iter().enumerate().rev() {
+                    let is_array = matches!(arg.kind, NodeKind::Array(_));
+                    if let Some(array_params) = &array_params {
+                        if array_params[idx] != is_array {
+                            let expected = if array_params[idx] { "an array" } else { "a number" };
+                            let msg = format!(
+                                "Argument {} of `{}` should be {}!",
+                                idx + 1,
+                                fn_name,
+                                expected
+                            );
+                            return Err(Error::Compile(msg, arg.span));
+                        }
+                    }
 
-                if *op_token == Token::PlusEquals
-                    || *op_token == Token::MinusEquals
-                    || *op_token == Token::StarEquals
-                    || *op_token == Token::SlashEquals
-                {
-                    op_equals = true;
+                    match &arg.kind {
+                        NodeKind::Array(name) => {
+                            self.operations.push(Instruction::PushArray(name.to_string()));
+                        }
+                        _ => self.compile_ast_node(arg)?,
+                    }
+                }
+                self.operations.push(Instruction::CallFn(fn_name.to_string(), args.len()));
+            }
+            NodeKind::Op(op_token @ (TokenKind::And | TokenKind::Or), children_nodes) => {
+                // Short-circuiting, the right side is only evaluated if it matters.
+                // Both sides are turned into 1 or 0 by jumping around.
+                let is_and = *op_token == TokenKind::And;
+
+                self.compile_ast_node(&children_nodes[0])?;
+                let left_is_zero = self.push_jump(Instruction::JumpIfZero(0));
+                let mut to_true = None;
+                if !is_and {
+                    to_true = Some(self.push_jump(Instruction::Jump(0)));
+                    self.patch_jump(left_is_zero, self.operations.len());
+                }
+
+                self.compile_ast_node(&children_nodes[1])?;
+                let right_is_zero = self.push_jump(Instruction::JumpIfZero(0));
+
+                if let Some(to_true) = to_true {
+                    self.patch_jump(to_true, self.operations.len());
                 }
+                self.operations.push(Instruction::PushConstant(Number::from_usize(1)));
+                let to_end = self.push_jump(Instruction::Jump(0));
+
+                if is_and {
+                    self.patch_jump(left_is_zero, self.operations.len());
+                }
+                self.patch_jump(right_is_zero, self.operations.len());
+                self.operations.push(Instruction::PushConstant(Number::zero()));
+                self.patch_jump(to_end, self.operations.len());
+            }
+            NodeKind::Op(op_token, children_nodes) => {
+                let child_count = children_nodes.len();
+                let is_negation = *op_token == TokenKind::Minus && child_count == 1;
 
                 for child_node in children_nodes.iter().rev() {
-                    self.compile_ast_node(child_node, equals);
+                    self.compile_ast_node(child_node)?;
                 }
 
                 if is_negation {
                     // Push negate
                     self.operations.push(Instruction::Negate);
-                } else if *op_token == Token::Plus && child_count == 1 {
+                } else if *op_token == TokenKind::Plus && child_count == 1 {
                     // Ignore plus
-                } else if let Token::FnCall(fn_name) = op_token {
-                    // Handle function call
-                    // Don't forget that the arguments get pushed to stack in reverse.
-                    self.operations.push(Instruction::CallFn(fn_name));
-                } else if !equals && !op_equals {
-                    self.push_op(*op_token);
-                } else if op_equals {
-                    if let AstNode::Ident(ident) = &children_nodes[0] {
-                        self.push_op(*op_token);
-                        self.operations.push(Instruction::Assign(ident));
-                    }
+                } else {
+                    self.push_op(*op_token, node)?;
                 }
             }
+            NodeKind::Define(..)
+            | NodeKind::Return(..)
+            | NodeKind::Block(..)
+            | NodeKind::If(..)
+            | NodeKind::While(..)
+            | NodeKind::For(..)
+            | NodeKind::Break
+            | NodeKind::Continue
+            | NodeKind::Halt
+            | NodeKind::Print(..) => {
+                let msg = String::from("Statements can't be used as expressions!");
+                return Err(Error::Compile(msg, node.span));
+            }
+            NodeKind::Str(..) => {
+                let msg = String::from("Strings can only be printed!");
+                return Err(Error::Compile(msg, node.span));
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_loop_body(&mut self, body: &AstNode) -> Result<Loop, Error> {
+        self.loops.push(Loop {
+            breaks: vec![],
+            continues: vec![],
+        });
+        let compiled = self.compile_statement(body);
+        let lp = self.loops.pop().unwrap();
+        compiled?;
+        Ok(lp)
+    }
+
+    fn patch_loop(&mut self, lp: Loop, continue_target: usize, break_target: usize) {
+        for jump in lp.continues {
+            self.patch_jump(jump, continue_target);
+        }
+        for jump in lp.breaks {
+            self.patch_jump(jump, break_target);
+        }
+    }
+
+    // ++ and --, the parser already made sure the operand is a variable or an element.
+    fn compile_step(
+        &mut self,
+        op: TokenKind,
+        operand: &AstNode,
+        postfix: bool,
+    ) -> Result<(), Error> {
+        let increment = op == TokenKind::Increment;
+        let instruction = match &operand.kind {
+            NodeKind::Ident(name) if increment => Instruction::Increment(name.to_string(), postfix),
+            NodeKind::Ident(name) => Instruction::Decrement(name.to_string(), postfix),
+            NodeKind::Element(name, index) => {
+                self.compile_ast_node(index)?;
+                if increment {
+                    Instruction::IncrementElement(name.to_string(), postfix)
+                } else {
+                    Instruction::DecrementElement(name.to_string(), postfix)
+                }
+            }
+            _ => unreachable!("bad operand for {:?}: {}", op, operand),
+        };
+        self.operations.push(instruction);
+        Ok(())
+    }
+
+    // Returns where the jump is, so its target can be patched later.
+    fn push_jump(&mut self, jump: Instruction) -> usize {
+        self.operations.push(jump);
+        self.operations.len() - 1
+    }
+
+    fn patch_jump(&mut self, jump_idx: usize, target: usize) {
+        match &mut self.operations[jump_idx] {
+            Instruction::Jump(old_target) | Instruction::JumpIfZero(old_target) => {
+                *old_target = target;
+            }
+            _ => unreachable!("not a jump at {}", jump_idx),
         }
     }
 
-    fn push_op(&mut self, op_token: Token) {
+    fn push_op(&mut self, op_token: TokenKind, node: &AstNode) -> Result<(), Error> {
         match op_token {
-            Token::Plus  | Token::PlusEquals  => self.operations.push(Instruction::Add),
-            Token::Minus | Token::MinusEquals => self.operations.push(Instruction::Sub),
-            Token::Star  | Token::StarEquals  => self.operations.push(Instruction::Mult),
-            Token::Slash | Token::SlashEquals => self.operations.push(Instruction::Div),
-            Token::Percent => self.operations.push(Instruction::Mod),
-            Token::Power   => self.operations.push(Instruction::Pow),
+            TokenKind::Plus  | TokenKind::PlusEquals  => self.operations.push(Instruction::Add),
+            TokenKind::Minus | TokenKind::MinusEquals => self.operations.push(Instruction::Sub),
+            TokenKind::Star  | TokenKind::StarEquals  => self.operations.push(Instruction::Mult),
+            TokenKind::Slash | TokenKind::SlashEquals => self.operations.push(Instruction::Div),
+            TokenKind::Percent | TokenKind::PercentEquals => self.operations.push(Instruction::Mod),
+            TokenKind::Power   | TokenKind::PowerEquals   => self.operations.push(Instruction::Pow),
+            TokenKind::EqualsEquals  => self.operations.push(Instruction::Equal),
+            TokenKind::NotEquals     => self.operations.push(Instruction::NotEqual),
+            TokenKind::Less          => self.operations.push(Instruction::Less),
+            TokenKind::LessEquals    => self.operations.push(Instruction::LessEqual),
+            TokenKind::Greater       => self.operations.push(Instruction::Greater),
+            TokenKind::GreaterEquals => self.operations.push(Instruction::GreaterEqual),
+            TokenKind::Not           => self.operations.push(Instruction::Not),
             _ => {
-                panic!("Unexpected token!");
+                let msg = format!("Unexpected operator: {:?}", op_token);
+                return Err(Error::Compile(msg, node.span));
             }
         }
+        Ok(())
     }
 }
+
+// The escapes `print` understands, like GNU bc. Strings can't have a `"` in
+// them, so that's `\q`. A backslash before anything else is dropped.
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('a') => result.push('\u{7}'),
+            Some('b') => result.push('\u{8}'),
+            Some('f') => result.push('\u{c}'),
+            Some('n') => result.push('\n'),
+            Some('q') => result.push('"'),
+            Some('r') => result.push('\r'),
+            Some('t') => result.push('\t'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+fn is_assignment(node: &AstNode) -> bool {
+    matches!(node.kind, NodeKind::Op(op, _) if is_assignment_op(op))
+}
@@ -0,0 +1,117 @@
+// Tab completion in the REPL. The names are the ones that exist when the
+// prompt is shown: variables, arrays, functions and the keywords.
+
+use crate::compiler::{Function, BUILTIN_FUNCTIONS};
+use crate::interpreter::Env;
+use crate::tokenizer::{is_alphanumeric, KEYWORDS};
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct Completion {
+    names: Vec<String>,
+}
+
+impl Completion {
+    // Functions come with their `(` and arrays with their `[`, so what's
+    // completed is always something that can be written there.
+    pub fn update(&mut self, env: &Env, functions: &HashMap<String, Function>) {
+        let builtins = BUILTIN_FUNCTIONS.iter().map(|(name, _)| *name);
+        let functions = functions.keys().map(String::as_str).chain(builtins);
+
+        let mut names: Vec<String> = env.vars.keys().cloned().collect();
+        names.extend(env.arrays.keys().map(|name| format!("{}[", name)));
+        names.extend(functions.map(|name| format!("{}(", name)));
+        names.extend(KEYWORDS.iter().map(|keyword| keyword.to_string()));
+        names.sort();
+        names.dedup();
+        self.names = names;
+    }
+}
+
+impl Completer for Completion {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        // The name being typed, names are a lowercase letter followed by
+        // letters, digits and underscores.
+        let start = line[..pos]
+            .char_indices()
+            .rfind(|&(_, ch)| !is_alphanumeric(ch))
+            .map_or(0, |(idx, ch)| idx + ch.len_utf8());
+        let prefix = &line[start..pos];
+        if !prefix.starts_with(|ch: char| ch.is_ascii_lowercase() || ch == '_') {
+            return Ok((pos, vec![]));
+        }
+
+        let candidates = self
+            .names
+            .iter()
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| Pair {
+                display: name.clone(),
+                replacement: name.clone(),
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+// Nothing but the completion is used, these are what rustyline needs.
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+#[cfg(test)]
+mod tests {
+    use super::Completion;
+    use crate::interpreter::Env;
+    use rustyline::completion::Completer;
+    use rustyline::history::DefaultHistory;
+    use rustyline::Context;
+    use std::collections::HashMap;
+
+    fn complete(line: &str) -> (usize, Vec<String>) {
+        let mut env = Env::default();
+        env.vars.insert(String::from("total"), "1".parse().unwrap());
+        env.arrays.insert(String::from("totals"), Default::default());
+        let mut completion = Completion::default();
+        Completion::update(&mut completion, &env, &HashMap::new());
+
+        let history = DefaultHistory::new();
+        let context = Context::new(&history);
+        let (start, pairs) = completion.complete(line, line.len(), &context).unwrap();
+        (start, pairs.into_iter().map(|pair| pair.replacement).collect())
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(complete("x = tot"), (4, vec![String::from("total"), String::from("totals[")]));
+        assert_eq!(complete("1+sq"), (2, vec![String::from("sqrt(")]));
+        assert_eq!(complete("totalRev").1, Vec::<String>::new());
+        assert_eq!(complete("3 + 4").1, Vec::<String>::new());
+    }
+
+    // The character before the name can be more than one byte.
+    #[test]
+    fn utf8() {
+        assert_eq!(complete("# naïve"), (6, vec![]));
+        assert_eq!(complete("\"é\"; tot").0, 6);
+        assert_eq!(complete("ï").1, Vec::<String>::new());
+    }
+}
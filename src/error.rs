@@ -0,0 +1,95 @@
+use crate::tokenizer::Span;
+use std::fmt;
+
+// Every stage of the pipeline reports its problems with one of these, so
+// `main` can print them and go on with the next input. Anything found before
+// running the code knows where in the source it is, a runtime error only
+// knows the statement it happened in once `main` adds it.
+#[derive(Debug)]
+pub enum Error {
+    Lexical(String, Span),
+    Unterminated(String, Span), // A string or a comment that the input ends in.
+    Syntax(String, Span),
+    Compile(String, Span),
+    Runtime(String, Option<Span>),
+    Warning(String, Span), // Doesn't stop anything, only for `-w`.
+}
+
+impl Error {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::Lexical(_, span)
+            | Error::Unterminated(_, span)
+            | Error::Syntax(_, span)
+            | Error::Compile(_, span)
+            | Error::Warning(_, span) => Some(*span),
+            Error::Runtime(_, span) => *span,
+        }
+    }
+
+    pub fn in_statement(self, span: Span) -> Error {
+        match self {
+            Error::Runtime(msg, None) => Error::Runtime(msg, Some(span)),
+            error => error,
+        }
+    }
+
+    // The error with the line it points to underlined, like rustc does. The
+    // file it's in is named, if it's in one:
+    //
+    //   Syntax error: Expected RightParen, found StatementEnd
+    //    --> sums.bc, line 1, column 5
+    //     |
+    //   1 | (1+2
+    //     |     ^
+    pub fn render(&self, source: &str, name: Option<&str>) -> String {
+        let span = match self.span() {
+            Some(span) => span,
+            None => return self.to_string(),
+        };
+
+        let line_start = source[..span.start].rfind('\n').map_or(0, |idx| idx + 1);
+        let line_text = source[line_start..].lines().next().unwrap_or("");
+        let line_end = line_start + line_text.len();
+
+        // Keep the tabs so the caret lines up with the text above it.
+        let padding: String = source[line_start..span.start]
+            .chars()
+            .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect();
+        let underlined = source[span.start..span.end.min(line_end).max(span.start)]
+            .chars()
+            .count();
+
+        let gutter = " ".repeat(span.line.to_string().len());
+        let file = name.map_or(String::new(), |name| format!("{}, ", name));
+        format!(
+            "{}\n{}--> {}line {}, column {}\n{} |\n{} | {}\n{} | {}^{}",
+            self,
+            gutter,
+            file,
+            span.line,
+            span.column,
+            gutter,
+            span.line,
+            line_text,
+            gutter,
+            padding,
+            "~".repeat(underlined.saturating_sub(1))
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Lexical(msg, _) | Error::Unterminated(msg, _) => {
+                write!(f, "Lexical error: {}", msg)
+            }
+            Error::Syntax(msg, _) => write!(f, "Syntax error: {}", msg),
+            Error::Compile(msg, _) => write!(f, "Compile error: {}", msg),
+            Error::Runtime(msg, _) => write!(f, "Runtime error: {}", msg),
+            Error::Warning(msg, _) => write!(f, "Warning: {}", msg),
+        }
+    }
+}
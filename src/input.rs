@@ -0,0 +1,147 @@
+// Collecting lines until they make something that can run, for the REPL and
+// for files and pipes, which run each statement as soon as it's read.
+//
+// Each line is scanned once as it comes in for what it leaves open: braces,
+// parentheses, a string, a comment, an operator or a backslash at the end.
+// The parser only gets the input once nothing is, so a long function body
+// isn't parsed again for every one of its lines.
+
+use crate::error::Error;
+use crate::parser::{AstNode, NodeKind, Parser};
+use crate::tokenizer::tokens_from_text;
+
+#[derive(Default)]
+pub struct Input {
+    text: String,
+    depth: usize, // Braces, parentheses and brackets left open.
+    in_string: bool,
+    in_comment: bool,
+    continued: bool, // The last line that has code on it ends with an operator or a backslash.
+}
+
+#[derive(PartialEq)]
+pub enum Readiness {
+    Incomplete,
+    Complete,
+    OpenIf, // Complete, but an `else` on the next line would still go with it.
+}
+
+impl Input {
+    pub fn push_line(&mut self, line: &str) {
+        self.text.push_str(line);
+        self.text.push('\n');
+        self.scan(line);
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Input::default();
+    }
+
+    // Incomplete when something is left open, or when the parser runs out of
+    // tokens before a `}`, a `)`, the right side of an operator...
+    pub fn readiness(&self) -> Readiness {
+        if self.depth > 0 || self.in_string || self.in_comment || self.continued {
+            return Readiness::Incomplete;
+        }
+
+        let (tokens, errors) = tokens_from_text(&self.text);
+        if errors.iter().any(|error| matches!(error, Error::Unterminated(..))) {
+            return Readiness::Incomplete;
+        }
+        let end = match tokens.last() {
+            Some(token) => token.span,
+            None => return Readiness::Complete,
+        };
+        let (statements, errors) = Parser::new(tokens).parse();
+        if errors.iter().any(|error| error.span() == Some(end)) {
+            Readiness::Incomplete
+        } else if statements.last().is_some_and(ends_with_open_if) {
+            Readiness::OpenIf
+        } else {
+            Readiness::Complete
+        }
+    }
+
+    fn scan(&mut self, line: &str) {
+        let chars: Vec<char> = line.chars().collect();
+        // The last two characters of code, the first one only if they're
+        // next to each other: `x++` doesn't go on, `x +` does.
+        let mut tail = (None, None);
+        let mut tail_idx = None;
+
+        let mut idx = 0;
+        while let Some(&ch) = chars.get(idx) {
+            let next = chars.get(idx + 1).copied();
+            idx += 1;
+            if self.in_comment {
+                if ch == '*' && next == Some('/') {
+                    self.in_comment = false;
+                    idx += 1;
+                }
+                continue;
+            }
+            if self.in_string {
+                if ch == '"' {
+                    self.in_string = false;
+                    tail = (None, Some(ch));
+                }
+                continue;
+            }
+
+            match ch {
+                '"' => self.in_string = true,
+                '/' if next == Some('*') => {
+                    self.in_comment = true;
+                    idx += 1;
+                    continue;
+                }
+                '#' => break,
+                '{' | '(' | '[' => self.depth += 1,
+                '}' | ')' | ']' => self.depth = self.depth.saturating_sub(1),
+                _ => {}
+            }
+            if !ch.is_whitespace() {
+                let adjacent = tail_idx.is_some_and(|last| last + 2 == idx);
+                tail = (if adjacent { tail.1 } else { None }, Some(ch));
+                tail_idx = Some(idx - 1);
+            }
+        }
+
+        let open = self.in_string || self.in_comment;
+        let backslash = !open && line.ends_with('\\');
+        // A line without code doesn't change whether the one before goes on.
+        if tail.1.is_some() {
+            self.continued = backslash || (!open && continues_line(tail));
+        } else {
+            self.continued |= backslash;
+        }
+    }
+}
+
+// The same as the tokenizer's `continues_line`, from the characters.
+fn continues_line(tail: (Option<char>, Option<char>)) -> bool {
+    match tail {
+        (Some('+'), Some('+')) | (Some('-'), Some('-')) => false,
+        (_, Some(ch)) => "+-*/^%=<>!&|,".contains(ch),
+        _ => false,
+    }
+}
+
+// Whether an `else` after the statement would belong to an `if` in it.
+fn ends_with_open_if(node: &AstNode) -> bool {
+    match &node.kind {
+        NodeKind::If(_, _, None) => true,
+        NodeKind::If(_, _, Some(body)) | NodeKind::While(_, body) | NodeKind::For(.., body) => {
+            ends_with_open_if(body)
+        }
+        _ => false,
+    }
+}
@@ -1,121 +1,453 @@
-use crate::compiler::Instruction;
-use std::collections::HashMap;
+use crate::compiler::{arity_error, builtin_arity, Function, Instruction};
+use crate::error::Error;
+use crate::mathlib::{self, MathFn};
+use crate::number::Number;
+use std::collections::{BTreeMap, HashMap};
 
-pub struct Vm<'source> {
-    ops: Vec<Instruction<'source>>,
-    env: &'source mut HashMap<String, f64>, // NOTE: The map lives longer than the 'source, but the vm doesn't.
+// Biggest index an array can take, same as GNU bc's BC_DIM_MAX.
+const MAX_ARRAY_INDEX: usize = 16777215;
+// Same as GNU bc's BC_BASE_MAX, input bases stop at 16 like POSIX wants.
+const MAX_OBASE: usize = 2147483647;
+const MAX_IBASE: usize = 16;
+// Printed numbers are broken into lines this long, the backslash included.
+const LINE_LENGTH: usize = 70;
+// GNU bc's BC_SCALE_MAX.
+const MAX_SCALE: usize = 2147483647;
+// Calls nest on the Rust stack, this keeps a runaway recursion from
+// overflowing it. `main` runs on a thread with a stack big enough for it.
+const MAX_CALL_DEPTH: usize = 5000;
+
+// Variables and arrays live in separate namespaces, `a` and `a[]` are
+// different things.
+#[derive(Default)]
+pub struct Env {
+    pub vars: HashMap<String, Number>,
+    pub arrays: HashMap<String, Array>,
+}
+
+// Only the elements that were set are kept, by index. Any index up to
+// `MAX_ARRAY_INDEX` can be written without the ones before it taking room.
+pub type Array = BTreeMap<usize, Number>;
+
+pub struct Vm<'env> {
+    ops: Vec<Instruction>,
+    env: &'env mut Env, // NOTE: The maps live longer than the vm.
+    fn_env: &'env HashMap<String, Function>,
+    halted: bool,
+    depth: usize, // How many calls deep it is.
+}
+
+// Arrays only get on the stack when they're passed to a function.
+enum Value {
+    Number(Number),
+    Array(Array),
 }
 
-impl<'source> Vm<'source> {
+// What a function call has to undo when it returns. Parameters and `auto`
+// variables are dynamically scoped like in bc: they shadow the variables with
+// the same name until the function returns, callees included.
+struct Frame {
+    saved: Vec<(String, Option<Number>)>,
+    saved_arrays: Vec<(String, Option<Array>)>,
+}
+
+impl<'env> Vm<'env> {
     pub fn new(
-        mut ops: Vec<Instruction<'source>>,
-        env: &'source mut HashMap<String, f64>,
-        // fn_env: &'source mut HashMap<String, f64>, // TODO add an environment for functions? Also check for arity of functions too!
-    ) -> Vm<'source> {
-        ops.reverse();
-        Self { ops, env }
+        ops: Vec<Instruction>,
+        env: &'env mut Env,
+        fn_env: &'env HashMap<String, Function>,
+    ) -> Vm<'env> {
+        Self {
+            ops,
+            env,
+            fn_env,
+            halted: false,
+            depth: 0,
+        }
     }
 
-    pub fn interpret(&mut self) {
-        let mut stack: Vec<f64> = vec![];
+    pub fn interpret(&mut self) -> Result<(), Error> {
+        let ops = std::mem::take(&mut self.ops);
+        let mut stack: Vec<Value> = vec![];
+        self.execute(&ops, &mut stack)
+    }
 
-        let pop = |stack: &mut Vec<f64>| -> f64 {
-            match stack.pop() {
-                Some(value) => value,
-                None => {
-                    panic!("Error: No value on the stack! Must be a problem with the parser?");
-                }
-            }
-        };
+    // A `halt` ran, bc should stop.
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
 
-        loop {
-            let operation = self.ops.pop();
-            if operation.is_none() {
-                break;
-            }
-            let operation = operation.unwrap();
+    fn execute(&mut self, ops: &[Instruction], stack: &mut Vec<Value>) -> Result<(), Error> {
+        let mut pc = 0;
+        while let Some(operation) = ops.get(pc) {
+            pc += 1;
+            let scale = self.scale();
 
             match operation {
                 Instruction::Add => {
-                    let a = pop(&mut stack);
-                    let b = pop(&mut stack);
+                    let a = pop(stack)?;
+                    let b = pop(stack)?;
 
-                    stack.push(a + b);
+                    stack.push(Value::Number(a.add(&b)));
                 }
                 Instruction::Sub => {
-                    let a = pop(&mut stack);
-                    let b = pop(&mut stack);
+                    let a = pop(stack)?;
+                    let b = pop(stack)?;
 
-                    stack.push(a - b);
+                    stack.push(Value::Number(a.sub(&b)));
                 }
                 Instruction::Negate => {
-                    let num = pop(&mut stack);
+                    let num = pop(stack)?;
 
-                    stack.push(-num);
+                    stack.push(Value::Number(num.negated()));
                 }
                 Instruction::Mult => {
-                    let a = pop(&mut stack);
-                    let b = pop(&mut stack);
+                    let a = pop(stack)?;
+                    let b = pop(stack)?;
 
-                    stack.push(a * b);
+                    stack.push(Value::Number(a.mul(&b, scale)));
                 }
                 Instruction::Pow => {
-                    let a = pop(&mut stack);
-                    let b = pop(&mut stack);
+                    let a = pop(stack)?;
+                    let b = pop(stack)?;
 
-                    stack.push(a.powf(b));
+                    stack.push(Value::Number(a.pow(&b, scale).map_err(runtime_error)?));
                 }
                 Instruction::Div => {
-                    let a = pop(&mut stack);
-                    let b = pop(&mut stack);
+                    let a = pop(stack)?;
+                    let b = pop(stack)?;
 
-                    stack.push(a / b);
+                    stack.push(Value::Number(a.div(&b, scale).map_err(runtime_error)?));
                 }
                 Instruction::Mod => {
-                    let a = pop(&mut stack);
-                    let b = pop(&mut stack);
+                    let a = pop(stack)?;
+                    let b = pop(stack)?;
+
+                    stack.push(Value::Number(a.rem(&b, scale).map_err(runtime_error)?));
+                }
+                Instruction::Equal
+                | Instruction::NotEqual
+                | Instruction::Less
+                | Instruction::LessEqual
+                | Instruction::Greater
+                | Instruction::GreaterEqual => {
+                    let a = pop(stack)?;
+                    let b = pop(stack)?;
 
-                    stack.push(a % b);
+                    let result = match operation {
+                        Instruction::Equal => a == b,
+                        Instruction::NotEqual => a != b,
+                        Instruction::Less => a < b,
+                        Instruction::LessEqual => a <= b,
+                        Instruction::Greater => a > b,
+                        _ => a >= b,
+                    };
+                    stack.push(Value::Number(Number::from_usize(result as usize)));
+                }
+                Instruction::Not => {
+                    let num = pop(stack)?;
+
+                    stack.push(Value::Number(Number::from_usize(num.is_zero() as usize)));
                 }
                 Instruction::PushConstant(num) => {
-                    stack.push(num);
+                    stack.push(Value::Number(num.clone()));
+                }
+                Instruction::PushNumber(number_str) => {
+                    let num = Number::from_str_radix(number_str, self.ibase());
+                    stack.push(Value::Number(num));
                 }
-                Instruction::GetVal(val_ident) => match self.env.get(val_ident) {
-                    Some(val) => stack.push(*val),
-                    None => stack.push(0.0),
+                Instruction::GetVal(val_ident) => match self.env.vars.get(val_ident) {
+                    Some(val) => stack.push(Value::Number(val.clone())),
+                    None => stack.push(Value::Number(Number::zero())),
                 }
                 Instruction::Assign(val_ident) => {
-                    let val = pop(&mut stack);
-                    self.env.insert(String::from(val_ident), val);
-                    stack.push(val);
-                }
-                Instruction::CallFn(fn_name) => {
-                    // TODO Add the ability to define functions
-                    // and check against the definitions here.
-                    let val = pop(&mut stack);
-
-                    if fn_name == "sin" {
-                        let result = val.sin();
-                        stack.push(result);
-                    } else if fn_name == "cos" {
-                        let result = val.cos();
-                        stack.push(result);
+                    let val = pop(stack)?;
+                    check_special_var(val_ident, &val)?;
+                    self.env.vars.insert(val_ident.clone(), val.clone());
+                    stack.push(Value::Number(val));
+                }
+                Instruction::GetElement(name) => {
+                    let idx = array_index(&pop(stack)?)?;
+                    let val = match self.env.arrays.get(name).and_then(|array| array.get(&idx)) {
+                        Some(val) => val.clone(),
+                        None => Number::zero(),
+                    };
+                    stack.push(Value::Number(val));
+                }
+                Instruction::AssignElement(name) => {
+                    let idx = array_index(&pop(stack)?)?;
+                    let val = pop(stack)?;
+                    *self.element(name, idx) = val.clone();
+                    stack.push(Value::Number(val));
+                }
+                Instruction::Increment(name, postfix) | Instruction::Decrement(name, postfix) => {
+                    let old = self.env.vars.get(name).cloned().unwrap_or_else(Number::zero);
+                    let new = stepped(operation, &old);
+                    check_special_var(name, &new)?;
+                    self.env.vars.insert(name.clone(), new.clone());
+                    stack.push(Value::Number(if *postfix { old } else { new }));
+                }
+                Instruction::IncrementElement(name, postfix)
+                | Instruction::DecrementElement(name, postfix) => {
+                    let idx = array_index(&pop(stack)?)?;
+                    let element = self.element(name, idx);
+                    let old = element.clone();
+                    let new = stepped(operation, &old);
+                    *element = new.clone();
+                    stack.push(Value::Number(if *postfix { old } else { new }));
+                }
+                Instruction::PushArray(name) => {
+                    let array = self.env.arrays.get(name).cloned().unwrap_or_default();
+                    stack.push(Value::Array(array));
+                }
+                Instruction::CallFn(fn_name, arg_count) => {
+                    let fn_env = self.fn_env;
+                    let expected = match fn_env.get(fn_name) {
+                        Some(function) => Some(function.params.len()),
+                        None => builtin_arity(fn_name),
+                    };
+                    match expected {
+                        Some(expected) if expected != *arg_count => {
+                            let msg = arity_error(fn_name, expected, *arg_count);
+                            return Err(runtime_error(&msg));
+                        }
+                        Some(_) => {}
+                        None => {
+                            let msg = format!("Function `{}` is not defined!", fn_name);
+                            return Err(runtime_error(&msg));
+                        }
+                    }
+
+                    if let Some(function) = fn_env.get(fn_name) {
+                        self.call(function, stack)?;
+                        if self.halted {
+                            return Ok(());
+                        }
+                        continue;
+                    }
+
+                    let val = pop(stack)?;
+
+                    // The same as the math library's `s` and `c`.
+                    if fn_name == "sin" || fn_name == "cos" {
+                        let math_fn = if fn_name == "sin" { MathFn::Sine } else { MathFn::Cosine };
+                        let result = mathlib::evaluate(math_fn, &[val], scale);
+                        stack.push(Value::Number(result.map_err(runtime_error)?));
                     } else if fn_name == "sqrt" {
-                        let result = val.sqrt();
-                        stack.push(result);
-                    } else {
-                        error("Unknown function name!");
+                        stack.push(Value::Number(val.sqrt(scale).map_err(runtime_error)?));
+                    } else if fn_name == "scale" {
+                        stack.push(Value::Number(Number::from_usize(val.scale())));
+                    } else if fn_name == "length" {
+                        stack.push(Value::Number(Number::from_usize(val.length())));
+                    }
+                }
+                Instruction::Math(math_fn) => {
+                    let args = (0..math_fn.params().len())
+                        .map(|_| pop(stack))
+                        .collect::<Result<Vec<Number>, Error>>()?;
+                    let result = mathlib::evaluate(*math_fn, &args, scale);
+                    stack.push(Value::Number(result.map_err(runtime_error)?));
+                }
+                Instruction::Return => {
+                    return Ok(());
+                }
+                Instruction::Halt => {
+                    self.halted = true;
+                    return Ok(());
+                }
+                Instruction::Print => {
+                    println!("{}", wrapped(&pop(stack)?.to_string_radix(self.obase())));
+                }
+                Instruction::Pop => {
+                    pop(stack)?;
+                }
+                Instruction::Duplicate => {
+                    let val = pop(stack)?;
+                    stack.push(Value::Number(val.clone()));
+                    stack.push(Value::Number(val));
+                }
+                Instruction::Swap => {
+                    let a = pop(stack)?;
+                    let b = pop(stack)?;
+                    stack.push(Value::Number(a));
+                    stack.push(Value::Number(b));
+                }
+                Instruction::Write => {
+                    print!("{}", wrapped(&pop(stack)?.to_string_radix(self.obase())));
+                }
+                Instruction::WriteString(text) => {
+                    print!("{}", text);
+                }
+                Instruction::Jump(target) => {
+                    pc = *target;
+                }
+                Instruction::JumpIfZero(target) => {
+                    if pop(stack)?.is_zero() {
+                        pc = *target;
                     }
                 }
             }
         }
-        let val = stack.pop();
-        if val.is_some() {
-            println!("{}", val.unwrap());
+        Ok(())
+    }
+
+    fn call(&mut self, function: &Function, stack: &mut Vec<Value>) -> Result<(), Error> {
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(runtime_error("recursion too deep"));
+        }
+
+        // The arguments were pushed last to first, so the first one is on top.
+        let mut frame = Frame {
+            saved: vec![],
+            saved_arrays: vec![],
+        };
+        for param in &function.params {
+            let name = param.name.clone();
+            if param.is_array {
+                let array = pop_array(stack)?;
+                let old_array = self.env.arrays.insert(name.clone(), array);
+                frame.saved_arrays.push((name, old_array));
+            } else {
+                let val = pop(stack)?;
+                let old_val = self.env.vars.insert(name.clone(), val);
+                frame.saved.push((name, old_val));
+            }
+        }
+        for auto in &function.autos {
+            let name = auto.name.clone();
+            if auto.is_array {
+                let old_array = self.env.arrays.insert(name.clone(), Array::new());
+                frame.saved_arrays.push((name, old_array));
+            } else {
+                let old_val = self.env.vars.insert(name.clone(), Number::zero());
+                frame.saved.push((name, old_val));
+            }
+        }
+
+        let mut fn_stack = vec![];
+        self.depth += 1;
+        let result = self.execute(&function.ops, &mut fn_stack);
+        self.depth -= 1;
+
+        // The caller's values come back even if the function failed.
+        for (name, old_val) in frame.saved.into_iter().rev() {
+            match old_val {
+                Some(val) => self.env.vars.insert(name, val),
+                None => self.env.vars.remove(&name),
+            };
+        }
+        for (name, old_array) in frame.saved_arrays.into_iter().rev() {
+            match old_array {
+                Some(array) => self.env.arrays.insert(name, array),
+                None => self.env.arrays.remove(&name),
+            };
+        }
+
+        result?;
+        if self.halted {
+            return Ok(());
+        }
+        stack.push(Value::Number(pop(&mut fn_stack)?));
+        Ok(())
+    }
+
+    // The element is made when it's first written to.
+    fn element(&mut self, name: &str, idx: usize) -> &mut Number {
+        let array = self.env.arrays.entry(name.to_string()).or_default();
+        array.entry(idx).or_insert_with(Number::zero)
+    }
+
+    fn scale(&self) -> usize {
+        match self.env.vars.get("scale") {
+            Some(scale) => scale.to_usize().unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    fn ibase(&self) -> usize {
+        match self.env.vars.get("ibase") {
+            Some(ibase) => ibase.to_usize().unwrap_or(10),
+            None => 10,
         }
     }
+
+    fn obase(&self) -> usize {
+        match self.env.vars.get("obase") {
+            Some(obase) => obase.to_usize().unwrap_or(10),
+            None => 10,
+        }
+    }
+}
+
+fn pop(stack: &mut Vec<Value>) -> Result<Number, Error> {
+    match stack.pop() {
+        Some(Value::Number(value)) => Ok(value),
+        Some(Value::Array(_)) => Err(runtime_error("Expected a number, found an array!")),
+        None => Err(runtime_error(
+            "No value on the stack! Must be a problem with the compiler?",
+        )),
+    }
+}
+
+fn pop_array(stack: &mut Vec<Value>) -> Result<Array, Error> {
+    match stack.pop() {
+        Some(Value::Array(array)) => Ok(array),
+        Some(Value::Number(_)) => Err(runtime_error("Expected an array, found a number!")),
+        None => Err(runtime_error(
+            "No value on the stack! Must be a problem with the compiler?",
+        )),
+    }
+}
+
+// The value after one of the ++ or -- instructions.
+fn stepped(operation: &Instruction, val: &Number) -> Number {
+    match operation {
+        Instruction::Increment(..) | Instruction::IncrementElement(..) => {
+            val.add(&Number::from_usize(1))
+        }
+        _ => val.sub(&Number::from_usize(1)),
+    }
+}
+
+// `scale`, `ibase` and `obase` are only read, so a bad value is caught when
+// it's set.
+fn check_special_var(name: &str, val: &Number) -> Result<(), Error> {
+    let (min, max) = match name {
+        "scale" => (0, MAX_SCALE),
+        "ibase" => (2, MAX_IBASE),
+        "obase" => (2, MAX_OBASE),
+        _ => return Ok(()),
+    };
+    match val.to_usize() {
+        Some(value) if (min..=max).contains(&value) && *val >= Number::zero() => Ok(()),
+        _ => Err(runtime_error(&format!(
+            "Invalid {}: {} (must be between {} and {})",
+            name, val, min, max
+        ))),
+    }
+}
+
+// Like bc, a long number goes on over several lines, each but the last one
+// ending with a backslash. bc reads it back the same.
+fn wrapped(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let lines: Vec<String> = chars
+        .chunks(LINE_LENGTH - 1)
+        .map(|line| line.iter().collect())
+        .collect();
+    lines.join("\\\n")
+}
+
+fn array_index(idx: &Number) -> Result<usize, Error> {
+    match idx.to_usize() {
+        Some(idx) if idx <= MAX_ARRAY_INDEX => Ok(idx),
+        _ => Err(runtime_error(&format!(
+            "Array index out of bounds: {} (must be between 0 and {})",
+            idx, MAX_ARRAY_INDEX
+        ))),
+    }
 }
 
-fn error(error_text: &str) {
-    println!("Error: {}", error_text);
+fn runtime_error(msg: &str) -> Error {
+    Error::Runtime(msg.to_string(), None)
 }
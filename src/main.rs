@@ -1,75 +1,400 @@
+mod commands;
 mod compiler;
+mod completion;
+mod error;
+mod input;
 mod interpreter;
+mod mathlib;
+mod number;
+mod options;
 mod parser;
+mod posix;
+mod state;
 mod tokenizer;
 
 use crate::compiler::*;
+use crate::completion::Completion;
+use crate::error::Error;
+use crate::input::{Input, Readiness};
 use crate::interpreter::*;
+use crate::number::Number;
+use crate::options::*;
 use crate::parser::*;
 use crate::tokenizer::*;
 use std::collections::HashMap;
-use std::io::{stdin, stdout, Write};
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{CompletionType, Config, Editor};
+use std::io::{self, stdin, BufRead, BufReader, IsTerminal};
 
 use std::env;
 use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+
+const HISTORY_FILE: &str = ".bc_history";
+const HISTORY_SIZE: usize = 1000;
+// Function calls nest on the stack, the default 8 MB isn't enough for the
+// deepest recursion the vm allows.
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+// Everything that lives from one input to the next.
+struct Session {
+    env: Env,
+    functions: HashMap<String, Function>,
+    extensions: Extensions,
+    mathlib: bool,
+    quit: bool, // A `quit` was read or a `halt` ran.
+}
 
 fn main() {
-    let mut user_input = String::new();
-    let mut map: HashMap<String, f64> = HashMap::new();
-    map.insert(String::from("debug"), 0.0);
-    println!("< bc-r: a bc clone - 0.0.3 >");
+    let bc = thread::Builder::new().stack_size(STACK_SIZE).spawn(bc);
+    match bc.map(|handle| handle.join()) {
+        Ok(Ok(())) => {}
+        // The panic was already printed.
+        Ok(Err(_)) => process::exit(101),
+        Err(error) => {
+            eprintln!("Couldn't start bc: {}", error);
+            process::exit(1);
+        }
+    }
+}
+
+fn bc() {
+    // First arg is executable location.
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match Options::parse(env::var("BC_ENV_ARGS").ok(), args) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{}\n\n{}", msg, USAGE);
+            process::exit(1);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+    if options.version {
+        println!("bc-r {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
+
+    let mut session = Session::new(options.extensions, options.mathlib);
+    let mut failed = false;
+    if let Some(path) = &options.load {
+        failed |= !session.load(path);
+    }
+
+    // When the input is a pipe or a file there's nobody to greet, and the
+    // output should only be the results.
+    let interactive = options.interactive || stdin().is_terminal();
+    if interactive && !options.quiet {
+        println!("< bc-r: a bc clone - {} >", env!("CARGO_PKG_VERSION"));
+    }
+
+    for source in &options.sources {
+        if session.quit {
+            break;
+        }
+        let (name, ok) = match source {
+            Source::Expression(expression) => {
+                (expression, Ok(session.run_and_report(expression, None, 1)))
+            }
+            Source::File(path) if path == "-" => (path, session.run_lines(stdin().lock(), None)),
+            Source::File(path) => {
+                let file = fs::File::open(path);
+                let reader = file.map(BufReader::new);
+                (path, reader.and_then(|reader| session.run_lines(reader, Some(path))))
+            }
+        };
+        match ok {
+            Ok(ok) => failed |= !ok,
+            Err(error) => {
+                eprintln!("Couldn't read `{}`: {}", name, error);
+                failed = true;
+            }
+        }
+    }
 
-    let args: Vec<String> = env::args().collect();
+    // With -e or -f it's just what was on the command line.
+    if session.quit {
+        // Nothing more to read.
+    } else if options.read_stdin && interactive {
+        session.repl();
+    } else if options.read_stdin {
+        match session.run_lines(stdin().lock(), None) {
+            Ok(ok) => failed |= !ok,
+            Err(error) => {
+                eprintln!("Couldn't read the input: {}", error);
+                failed = true;
+            }
+        }
+    }
 
-    if args.len() > 1 {
-        // First arg is executable location.
-        let contents = fs::read_to_string(&args[1]).expect("Couldn't read the source file.");
-        user_input.push_str(&contents);
+    if let Some(path) = &options.save {
+        failed |= !session.save(path);
     }
+    if failed {
+        process::exit(1);
+    }
+}
+
+// From the start of the first line `span` is on to the end of its last one.
+fn lines_of(source: &str, span: Span) -> std::ops::RangeInclusive<usize> {
+    let start = source[..span.start].rfind('\n').map_or(0, |idx| idx + 1);
+    let end = source[span.end..].find('\n').map_or(source.len(), |idx| span.end + idx);
+    start..=end
+}
 
-    loop {
-        if !(user_input.len() > 0) {
-            print!("> ");
-            let _ = stdout().flush();
+// `name` is the file `source` is from.
+fn report(source: &str, name: Option<&str>, errors: &[Error]) {
+    for error in errors {
+        eprintln!("{}", error.render(source, name));
+    }
+}
 
-            stdin()
-                .read_line(&mut user_input)
-                .expect("The input is weeeirrrrdddd. Use only ASCII characters for now.");
+// The history is kept between sessions in the home directory.
+fn history_path() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(HISTORY_FILE))
+}
 
-            // println!("You typed: {}", user_input);
+impl Session {
+    fn new(extensions: Extensions, mathlib: bool) -> Session {
+        let mut session = Session {
+            env: Env::default(),
+            functions: HashMap::new(),
+            extensions,
+            mathlib,
+            quit: false,
+        };
+        let vars = &mut session.env.vars;
+        vars.insert(String::from("scale"), Number::zero());
+        vars.insert(String::from("ibase"), Number::from_usize(10));
+        vars.insert(String::from("obase"), Number::from_usize(10));
 
-            // TODO This should be a intrinsic function at some point.
-            if user_input.contains("quit") {
-                break;
+        if mathlib {
+            mathlib::load(&mut session.functions);
+            vars.insert(String::from("scale"), Number::from_usize(mathlib::MATHLIB_SCALE));
+        }
+        session
+    }
+
+    fn repl(&mut self) {
+        let config = Config::builder()
+            .max_history_size(HISTORY_SIZE)
+            .map(|config| config.completion_type(CompletionType::List).build());
+        let mut editor = match config.and_then(Editor::<Completion, DefaultHistory>::with_config) {
+            Ok(editor) => editor,
+            Err(error) => {
+                eprintln!("Couldn't start the line editor: {}", error);
+                return;
             }
+        };
+
+        // There's no history file the first time.
+        let history = history_path();
+        if let Some(path) = &history {
+            let _ = editor.load_history(path);
         }
 
-        let tokens = tokens_from_text(&user_input);
+        editor.set_helper(Some(Completion::default()));
+
+        // Lines are collected until they make a whole program. An `if` runs
+        // right away, someone is waiting for it.
+        let mut input = Input::default();
+        loop {
+            let prompt = if input.is_empty() { "> " } else { "... " };
+            if let Some(completion) = editor.helper_mut() {
+                completion.update(&self.env, &self.functions);
+            }
+            match editor.readline(prompt) {
+                // `:vars` and the like, see `commands`.
+                Ok(line) if input.is_empty() && line.trim_start().starts_with(':') => {
+                    let _ = editor.add_history_entry(line.trim());
+                    self.command(line.trim());
+                    if self.quit {
+                        break;
+                    }
+                }
+                Ok(line) => {
+                    input.push_line(&line);
+                    if input.readiness() == Readiness::Incomplete {
+                        continue;
+                    }
+
+                    let _ = editor.add_history_entry(input.text().trim_end());
+                    self.run_and_report(input.text(), None, 1);
+                    input.clear();
+                    if self.quit {
+                        break;
+                    }
+                }
+                // Ctrl-C throws the input away, like in a shell.
+                Err(ReadlineError::Interrupted) => input.clear(),
+                Err(ReadlineError::Eof) => {
+                    // Whatever was left is reported as it is.
+                    if !input.is_empty() {
+                        self.run_and_report(input.text(), None, 1);
+                    }
+                    println!();
+                    break;
+                }
+                Err(error) => {
+                    eprintln!("Couldn't read the input: {}", error);
+                    break;
+                }
+            }
+        }
 
-        if map["debug"] > 0.5 {
-            println!("Tokens: {:?}", tokens);
+        if let Some(path) = &history {
+            if let Err(error) = editor.save_history(path) {
+                eprintln!("Couldn't save the history to `{}`: {}", path.display(), error);
+            }
         }
+    }
 
-        let parser = Parser::new(tokens);
-        let statements = parser.parse();
+    // Runs each statement as soon as all of it is read, like bc does: the
+    // output of a pipe comes as it goes, and an error only loses its own lines.
+    // Returns whether there weren't any errors. `name` is the file it's reading.
+    fn run_lines(&mut self, reader: impl BufRead, name: Option<&str>) -> io::Result<bool> {
+        let mut ok = true;
+        let mut input = Input::default();
+        let mut first_line = 1;
+        let mut open_if = false;
+        for line in reader.lines() {
+            let line = line?;
+            // An `if` waits for the next line with code on it, which may
+            // start with its `else`, like when the whole program is parsed.
+            if open_if {
+                let first = tokens_from_text(&line).0.first().map(|token| token.kind);
+                if !matches!(first, None | Some(TokenKind::EOF | TokenKind::Else)) {
+                    ok &= self.run_input(&mut input, name, &mut first_line);
+                    if self.quit {
+                        return Ok(ok);
+                    }
+                }
+            }
 
-        let mut ops = vec![];
-        for ast in statements {
-            if map["debug"] > 0.5 {
-                println!("AST: {}", ast);
+            input.push_line(&line);
+            match input.readiness() {
+                Readiness::Incomplete => open_if = false,
+                Readiness::OpenIf => open_if = true,
+                Readiness::Complete => {
+                    open_if = false;
+                    ok &= self.run_input(&mut input, name, &mut first_line);
+                    if self.quit {
+                        return Ok(ok);
+                    }
+                }
             }
+        }
+
+        // Whatever was left is reported as it is.
+        if !input.is_empty() {
+            ok &= self.run_input(&mut input, name, &mut first_line);
+        }
+        Ok(ok)
+    }
 
-            let compiler = Compiler::new(); // I don't like doing this every loop. TODO
-            ops.append(&mut compiler.compile(ast));
+    // Runs what `run_lines` collected and starts over, at the line after it.
+    fn run_input(&mut self, input: &mut Input, name: Option<&str>, first_line: &mut usize) -> bool {
+        let ok = self.run_and_report(input.text(), name, *first_line);
+        *first_line += input.text().matches('\n').count();
+        input.clear();
+        ok
+    }
+
+    // Prints the errors, returns whether there weren't any. `name` is the
+    // file `source` is from, `first_line` the line it starts on in there.
+    fn run_and_report(&mut self, source: &str, name: Option<&str>, first_line: usize) -> bool {
+        match self.run(source, name, first_line) {
+            Ok(()) => true,
+            Err(errors) => {
+                report(source, name, &errors);
+                false
+            }
         }
+    }
 
-        if map["debug"] > 0.5 {
-            println!("Ops: {:?}", ops);
+    fn run(
+        &mut self,
+        source: &str,
+        name: Option<&str>,
+        first_line: usize,
+    ) -> Result<(), Vec<Error>> {
+        let (tokens, errors) = tokens_from_line(source, first_line);
+        // The lines before a `quit` still run, so this only takes effect at the end.
+        let quit = tokens.iter().any(|token| token.kind == TokenKind::Quit);
+        let result = self.run_tokens(source, name, tokens, errors);
+        self.quit |= quit;
+        result
+    }
+
+    // `errors` are the ones the tokenizer found.
+    fn run_tokens(
+        &mut self,
+        source: &str,
+        name: Option<&str>,
+        tokens: Vec<Token>,
+        mut errors: Vec<Error>,
+    ) -> Result<(), Vec<Error>> {
+        if self.extensions != Extensions::Allowed {
+            let found = posix::extensions(source, &tokens);
+            let found = found.into_iter().map(|(what, span)| {
+                let msg = format!("POSIX bc doesn't have {}", what);
+                match self.extensions {
+                    Extensions::Rejected => Error::Syntax(msg, span),
+                    _ => Error::Warning(msg, span),
+                }
+            });
+            let found: Vec<Error> = found.collect();
+
+            if self.extensions == Extensions::Rejected && !found.is_empty() {
+                errors.extend(found);
+                return Err(errors);
+            }
+            for warning in found {
+                eprintln!("{}", warning.render(source, name));
+            }
         }
 
-        let mut vm = Vm::new(ops, &mut map); // I also don't like doing this every loop. TODO
-        vm.interpret();
+        // Nothing on a line with a character bc doesn't know runs, it may
+        // not be what was meant.
+        let (mut statements, parse_errors) = Parser::new(tokens).parse();
+        statements.retain(|statement| {
+            let lines = lines_of(source, statement.span);
+            !errors.iter().any(|error| error.span().is_some_and(|span| lines.contains(&span.start)))
+        });
+        errors.extend(parse_errors);
+        errors.sort_by_key(|error| error.span().map(|span| span.start));
+
+        // Like bc, an error only stops the statement it happened in.
+        for statement in &statements {
+            let compiler = Compiler::new(&mut self.functions, source);
+            let ops = match compiler.compile(std::slice::from_ref(statement)) {
+                Ok(ops) => ops,
+                Err(error) => {
+                    errors.push(error);
+                    continue;
+                }
+            };
+
+            // I also don't like doing this every loop. TODO
+            let mut vm = Vm::new(ops, &mut self.env, &self.functions);
+            if let Err(error) = vm.interpret() {
+                errors.push(error.in_statement(statement.span));
+            }
+            if vm.halted() {
+                self.quit = true;
+                break;
+            }
+        }
 
-        user_input.clear();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
     }
 }
@@ -0,0 +1,314 @@
+// The POSIX math library, what `bc -l` loads: s, c, a, l, e and j.
+//
+// bc ships these as bc code, they're done natively here but with the same
+// tricks: shrink the argument until a power series converges fast, run the
+// series with some extra digits, then undo the shrinking. The results have
+// exactly `scale` digits after the point, like the originals.
+//
+// They go in as ordinary functions whose bodies are a single instruction, so
+// a script can still define its own `s` over them, like in bc.
+
+use crate::compiler::{Function, Instruction, Param};
+use crate::number::Number;
+use std::collections::HashMap;
+
+// Digits on top of `scale` that the series are run with, so the rounding
+// errors of the last few terms don't show in the result.
+const GUARD_DIGITS: usize = 10;
+
+// bc sets the scale to this when the library is loaded.
+pub const MATHLIB_SCALE: usize = 20;
+
+#[derive(Debug, Clone, Copy)]
+pub enum MathFn {
+    Sine,
+    Cosine,
+    Arctangent,
+    Log,
+    Exp,
+    Bessel,
+}
+
+impl MathFn {
+    pub fn params(self) -> &'static [&'static str] {
+        match self {
+            MathFn::Bessel => &["n", "x"],
+            _ => &["x"],
+        }
+    }
+}
+
+pub fn load(functions: &mut HashMap<String, Function>) {
+    let library = [
+        ("s", MathFn::Sine),
+        ("c", MathFn::Cosine),
+        ("a", MathFn::Arctangent),
+        ("l", MathFn::Log),
+        ("e", MathFn::Exp),
+        ("j", MathFn::Bessel),
+    ];
+
+    for (name, math_fn) in library {
+        let params: Vec<Param> = math_fn
+            .params()
+            .iter()
+            .map(|param| Param {
+                name: param.to_string(),
+                is_array: false,
+            })
+            .collect();
+
+        // Arguments go on the stack last to first, like for any other call.
+        let mut ops: Vec<Instruction> = params
+            .iter()
+            .rev()
+            .map(|param| Instruction::GetVal(param.name.clone()))
+            .collect();
+        ops.push(Instruction::Math(math_fn));
+        ops.push(Instruction::Return);
+
+        let function = Function {
+            params,
+            autos: vec![],
+            ops,
+            text: None,
+        };
+        functions.insert(name.to_string(), function);
+    }
+}
+
+// `args` are in the order of the parameters.
+pub fn evaluate(math_fn: MathFn, args: &[Number], scale: usize) -> Result<Number, &'static str> {
+    let result = match math_fn {
+        MathFn::Sine => sine(&args[0], scale)?,
+        MathFn::Cosine => cosine(&args[0], scale)?,
+        MathFn::Arctangent => arctangent(&args[0], scale + GUARD_DIGITS)?,
+        MathFn::Log => log(&args[0], scale)?,
+        MathFn::Exp => exp(&args[0], scale)?,
+        MathFn::Bessel => bessel(&args[0], &args[1], scale)?,
+    };
+    // The guard digits are off by a few units, which would show up as
+    // .99999999999999999999 where the answer is exactly one. So they're
+    // rounded off at half of them before dropping the rest.
+    let half_unit: Number = format!(".{}5", "0".repeat(scale + GUARD_DIGITS / 2))
+        .parse()
+        .unwrap_or_else(|_| Number::zero());
+    let rounded = if result < Number::zero() {
+        result.sub(&half_unit)
+    } else {
+        result.add(&half_unit)
+    };
+
+    // Dividing by one gives exactly `scale` digits, this is what bc does too.
+    rounded.div(&one(), scale)
+}
+
+fn one() -> Number {
+    Number::from_usize(1)
+}
+
+fn two() -> Number {
+    Number::from_usize(2)
+}
+
+// How many digits the integer part of `x` has, for the extra precision
+// that big arguments need.
+fn integer_digits(x: &Number) -> usize {
+    let integer = x.clone().truncated(0);
+    if integer.is_zero() {
+        0
+    } else {
+        integer.length()
+    }
+}
+
+// e^x. Halves x until it's at most one, sums the series, then squares the
+// result back up.
+fn exp(x: &Number, scale: usize) -> Result<Number, &'static str> {
+    if *x < Number::zero() {
+        let work_scale = scale + GUARD_DIGITS;
+        return one().div(&exp(&x.negated(), work_scale)?, work_scale);
+    }
+
+    // The result has about .44 * x digits before the point, which all have
+    // to be right for the ones after it to be right.
+    let magnitude = x.to_usize().ok_or("exponent too large")?;
+    let mut work_scale = scale + GUARD_DIGITS + magnitude * 44 / 100;
+
+    let mut x = x.clone();
+    let mut halvings = 0;
+    while x > one() {
+        x = x.div(&two(), work_scale)?;
+        halvings += 1;
+        work_scale += 1;
+    }
+
+    let mut sum = one();
+    let mut term = one();
+    let mut i = 1;
+    loop {
+        term = term.mul(&x, work_scale).div(&Number::from_usize(i), work_scale)?;
+        if term.is_zero() {
+            break;
+        }
+        sum = sum.add(&term);
+        i += 1;
+    }
+
+    for _ in 0..halvings {
+        sum = sum.mul(&sum, work_scale);
+    }
+    Ok(sum)
+}
+
+// ln(x). Takes square roots until x is near one, then sums the series of
+// 2 * atanh((x - 1) / (x + 1)). Every square root doubles the final factor.
+fn log(x: &Number, scale: usize) -> Result<Number, &'static str> {
+    if *x <= Number::zero() {
+        // What bc's library returns, it doesn't stop the script.
+        return Ok(one().sub(&Number::from_usize(10).pow(&Number::from_usize(scale), 0)?));
+    }
+
+    let work_scale = scale + GUARD_DIGITS + integer_digits(x);
+    let half: Number = ".5".parse().unwrap_or_else(|_| Number::zero());
+
+    let mut x = x.clone();
+    let mut factor = two();
+    while x >= two() {
+        factor = factor.mul(&two(), 0);
+        x = x.sqrt(work_scale)?;
+    }
+    while x <= half {
+        factor = factor.mul(&two(), 0);
+        x = x.sqrt(work_scale)?;
+    }
+
+    let n = x.sub(&one()).div(&x.add(&one()), work_scale)?;
+    let n_squared = n.mul(&n, work_scale);
+    let mut power = n.clone();
+    let mut sum = n;
+    let mut i = 3;
+    loop {
+        power = power.mul(&n_squared, work_scale);
+        let term = power.div(&Number::from_usize(i), work_scale)?;
+        if term.is_zero() {
+            break;
+        }
+        sum = sum.add(&term);
+        i += 2;
+    }
+
+    Ok(sum.mul(&factor, work_scale))
+}
+
+// atan(x), with `scale` already including the extra digits. Uses
+// atan(x) = 2 * atan(x / (1 + sqrt(1 + x^2))) until x is small, then the
+// alternating series.
+fn arctangent(x: &Number, scale: usize) -> Result<Number, &'static str> {
+    if *x < Number::zero() {
+        return Ok(arctangent(&x.negated(), scale)?.negated());
+    }
+
+    let small: Number = ".2".parse().unwrap_or_else(|_| Number::zero());
+    let mut x = x.clone();
+    let mut factor = one();
+    while x > small {
+        let root = one().add(&x.mul(&x, scale)).sqrt(scale)?;
+        x = x.div(&one().add(&root), scale)?;
+        factor = factor.mul(&two(), 0);
+    }
+
+    let x_squared = x.mul(&x, scale);
+    let mut power = x.clone();
+    let mut sum = x;
+    let mut i = 3;
+    let mut subtract = true;
+    loop {
+        power = power.mul(&x_squared, scale);
+        let term = power.div(&Number::from_usize(i), scale)?;
+        if term.is_zero() {
+            break;
+        }
+        sum = if subtract { sum.sub(&term) } else { sum.add(&term) };
+        subtract = !subtract;
+        i += 2;
+    }
+
+    Ok(sum.mul(&factor, scale))
+}
+
+// sin(x). Takes out the whole turns, then sums the series for what's left,
+// which is between -pi and pi.
+fn sine(x: &Number, scale: usize) -> Result<Number, &'static str> {
+    // Every digit before the point needs one more digit of pi.
+    let work_scale = scale + GUARD_DIGITS + integer_digits(x);
+    let pi = arctangent(&one(), work_scale)?.mul(&Number::from_usize(4), work_scale);
+    let turn = pi.mul(&two(), work_scale);
+
+    let turns = x.div(&turn, 0)?;
+    let mut x = x.sub(&turns.mul(&turn, work_scale));
+    if x > pi {
+        x = x.sub(&turn);
+    } else if x < pi.negated() {
+        x = x.add(&turn);
+    }
+
+    let x_squared = x.mul(&x, work_scale);
+    let mut term = x.clone();
+    let mut sum = x;
+    let mut i = 1;
+    loop {
+        let divisor = Number::from_usize((i + 1) * (i + 2));
+        term = term.mul(&x_squared, work_scale).div(&divisor, work_scale)?.negated();
+        if term.is_zero() {
+            break;
+        }
+        sum = sum.add(&term);
+        i += 2;
+    }
+
+    Ok(sum)
+}
+
+// cos(x) = sin(x + pi / 2), what bc does too.
+fn cosine(x: &Number, scale: usize) -> Result<Number, &'static str> {
+    let work_scale = scale + GUARD_DIGITS + integer_digits(x);
+    let pi = arctangent(&one(), work_scale)?.mul(&Number::from_usize(4), work_scale);
+    sine(&x.add(&pi.div(&two(), work_scale)?), scale)
+}
+
+// The Bessel function of the first kind, of integer order n:
+// sum of (-1)^k * (x / 2)^(2k + n) / (k! * (n + k)!)
+fn bessel(n: &Number, x: &Number, scale: usize) -> Result<Number, &'static str> {
+    let order = n.to_i64().ok_or("order too large")?;
+    let negate = order < 0 && order % 2 != 0;
+    let order = order.unsigned_abs() as usize;
+
+    // The terms get as big as about e^x before they get small, and they
+    // cancel each other out, so those digits have to be there too.
+    let magnitude = x.to_i64().ok_or("argument too large")?.unsigned_abs() as usize;
+    let work_scale = scale + GUARD_DIGITS + magnitude * 44 / 100;
+
+    let half_x = x.div(&two(), work_scale)?;
+    let minus_quarter_x_squared = half_x.mul(&half_x, work_scale).negated();
+
+    // The first term, (x / 2)^n / n!
+    let mut term = one();
+    for i in 1..=order {
+        term = term.mul(&half_x, work_scale).div(&Number::from_usize(i), work_scale)?;
+    }
+
+    let mut sum = term.clone();
+    let mut k = 1;
+    loop {
+        let divisor = Number::from_usize(k * (order + k));
+        term = term.mul(&minus_quarter_x_squared, work_scale).div(&divisor, work_scale)?;
+        if term.is_zero() {
+            break;
+        }
+        sum = sum.add(&term);
+        k += 1;
+    }
+
+    Ok(if negate { sum.negated() } else { sum })
+}
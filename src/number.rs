@@ -0,0 +1,552 @@
+// Arbitrary precision decimal numbers, the way bc does them.
+//
+// A number is an integer mantissa and a `scale`, the count of digits after
+// the decimal point: `digits * 10^-scale`. The digits are kept in base 10,
+// least significant first, so changing the scale of a number is just adding
+// or removing digits at the front of the vector.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone)]
+pub struct Number {
+    negative: bool,
+    digits: Vec<u8>, // Never has zeros at the end (most significant side). Empty means zero.
+    scale: usize,
+}
+
+impl Number {
+    pub fn zero() -> Self {
+        Self {
+            negative: false,
+            digits: vec![],
+            scale: 0,
+        }
+    }
+
+    pub fn from_usize(mut value: usize) -> Self {
+        let mut digits = vec![];
+        while value > 0 {
+            digits.push((value % 10) as u8);
+            value /= 10;
+        }
+        Self::from_parts(false, digits, 0)
+    }
+
+    /// Reads `text` in `base` like bc does: a digit too big for the base counts
+    /// as the biggest digit, unless the number is a single digit.
+    pub fn from_str_radix(text: &str, base: usize) -> Self {
+        let (integer_part, fraction_part) = text.split_once('.').unwrap_or((text, ""));
+        if text.len() == 1 {
+            return Self::from_usize(digit_value(text.chars().next().unwrap_or('0')));
+        }
+
+        let radix = Self::from_usize(base);
+        let digit = |ch: char| Self::from_usize(digit_value(ch).min(base - 1));
+
+        let mut integer = Self::zero();
+        for ch in integer_part.chars() {
+            integer = integer.mul_exact(&radix).add(&digit(ch));
+        }
+
+        let mut numerator = Self::zero();
+        let mut denominator = Self::from_usize(1);
+        for ch in fraction_part.chars() {
+            numerator = numerator.mul_exact(&radix).add(&digit(ch));
+            denominator = denominator.mul_exact(&radix);
+        }
+        let fraction = numerator
+            .div(&denominator, fraction_part.len())
+            .unwrap_or_else(|_| Self::zero());
+
+        integer.add(&fraction)
+    }
+
+    fn from_parts(negative: bool, mut digits: Vec<u8>, scale: usize) -> Self {
+        trim(&mut digits);
+        Self {
+            negative: negative && !digits.is_empty(),
+            digits,
+            scale,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+
+    /// Count of significant decimal digits, what bc's `length()` returns.
+    pub fn length(&self) -> usize {
+        if self.is_zero() {
+            self.scale.max(1)
+        } else {
+            self.digits.len().max(self.scale)
+        }
+    }
+
+    /// The integer part of the number, if it fits.
+    pub fn to_i64(&self) -> Option<i64> {
+        let mut value: i64 = 0;
+        for &digit in self.digits.iter().skip(self.scale).rev() {
+            value = value.checked_mul(10)?.checked_add(digit as i64)?;
+        }
+        Some(if self.negative { -value } else { value })
+    }
+
+    pub fn to_usize(&self) -> Option<usize> {
+        usize::try_from(self.to_i64()?).ok()
+    }
+
+    pub fn negated(&self) -> Number {
+        Self::from_parts(!self.negative, self.digits.clone(), self.scale)
+    }
+
+    /// Drops the digits after `scale`, bc never rounds.
+    pub fn truncated(mut self, scale: usize) -> Number {
+        if scale < self.scale {
+            let dropped = (self.scale - scale).min(self.digits.len());
+            self.digits.drain(..dropped);
+            self = Self::from_parts(self.negative, self.digits, scale);
+        }
+        self
+    }
+
+    pub fn add(&self, other: &Number) -> Number {
+        let scale = self.scale.max(other.scale);
+        let a = shift(&self.digits, scale - self.scale);
+        let b = shift(&other.digits, scale - other.scale);
+
+        if self.negative == other.negative {
+            return Self::from_parts(self.negative, add_digits(&a, &b), scale);
+        }
+
+        match cmp_digits(&a, &b) {
+            Ordering::Less => Self::from_parts(other.negative, sub_digits(&b, &a), scale),
+            _ => Self::from_parts(self.negative, sub_digits(&a, &b), scale),
+        }
+    }
+
+    pub fn sub(&self, other: &Number) -> Number {
+        self.add(&other.negated())
+    }
+
+    fn mul_exact(&self, other: &Number) -> Number {
+        Self::from_parts(
+            self.negative != other.negative,
+            mul_digits(&self.digits, &other.digits),
+            self.scale + other.scale,
+        )
+    }
+
+    pub fn mul(&self, other: &Number, scale: usize) -> Number {
+        let full_scale = self.scale + other.scale;
+        let result_scale = full_scale.min(scale.max(self.scale).max(other.scale));
+        self.mul_exact(other).truncated(result_scale)
+    }
+
+    pub fn div(&self, other: &Number, scale: usize) -> Result<Number, &'static str> {
+        if other.is_zero() {
+            return Err("Divide by zero");
+        }
+
+        // (A / 10^sa) / (B / 10^sb) * 10^scale == (A * 10^(sb + scale)) / (B * 10^sa)
+        let numerator = shift(&self.digits, other.scale + scale);
+        let denominator = shift(&other.digits, self.scale);
+        let (quotient, _) = divmod_digits(&numerator, &denominator);
+
+        Ok(Self::from_parts(self.negative != other.negative, quotient, scale))
+    }
+
+    pub fn rem(&self, other: &Number, scale: usize) -> Result<Number, &'static str> {
+        // bc defines a % b as a - (a / b) * b, with the division done in `scale`.
+        let quotient = self.div(other, scale)?;
+        Ok(self.sub(&quotient.mul_exact(other)))
+    }
+
+    pub fn pow(&self, exponent: &Number, scale: usize) -> Result<Number, &'static str> {
+        // Fractional parts of the exponent are ignored.
+        let exponent = exponent.to_i64().ok_or("exponent too large")?;
+        if exponent == 0 {
+            return Ok(Self::from_usize(1));
+        }
+
+        let mut result = Self::from_usize(1);
+        let mut base = self.clone();
+        let mut remaining = exponent.unsigned_abs();
+        while remaining > 0 {
+            if remaining & 1 == 1 {
+                result = result.mul_exact(&base);
+            }
+            remaining >>= 1;
+            if remaining > 0 {
+                base = base.mul_exact(&base);
+            }
+        }
+
+        if exponent < 0 {
+            Self::from_usize(1).div(&result, scale)
+        } else {
+            let full_scale = result.scale;
+            Ok(result.truncated(full_scale.min(scale.max(self.scale))))
+        }
+    }
+
+    /// The number written in `base`. Bases above 16 don't have enough
+    /// letters, so every digit is printed in decimal after a space, padded to
+    /// the width of the biggest digit: 25 in base 20 is ` 01 05`.
+    pub fn to_string_radix(&self, base: usize) -> String {
+        if base == 10 || self.is_zero() {
+            return self.to_string();
+        }
+
+        let radix = Self::from_usize(base);
+        let magnitude = Self::from_parts(false, self.digits.clone(), self.scale);
+        let mut integer = magnitude.clone().truncated(0);
+        let mut fraction = magnitude.sub(&integer);
+
+        let mut integer_digits = vec![];
+        while !integer.is_zero() {
+            let digit = integer.rem(&radix, 0).unwrap_or_else(|_| Self::zero());
+            integer_digits.push(digit.to_usize().unwrap_or(0));
+            integer = integer.div(&radix, 0).unwrap_or_else(|_| Self::zero());
+        }
+
+        // As many digits as it takes to be at least as precise as `scale`.
+        let mut fraction_digits = vec![];
+        let mut precision = Self::from_usize(1);
+        while precision.length() <= self.scale {
+            fraction = fraction.mul_exact(&radix);
+            let digit = fraction.clone().truncated(0);
+            fraction = fraction.sub(&digit);
+            fraction_digits.push(digit.to_usize().unwrap_or(0));
+            precision = precision.mul_exact(&radix);
+        }
+
+        let width = (base - 1).to_string().len();
+        let write_digit = |text: &mut String, digit: usize| {
+            if base <= 16 {
+                text.push(char::from_digit(digit as u32, 16).unwrap_or('0').to_ascii_uppercase());
+            } else {
+                text.push_str(&format!(" {:0width$}", digit, width = width));
+            }
+        };
+
+        let mut text = String::new();
+        if self.negative {
+            text.push('-');
+        }
+        for &digit in integer_digits.iter().rev() {
+            write_digit(&mut text, digit);
+        }
+        if self.scale > 0 {
+            text.push('.');
+            for &digit in &fraction_digits {
+                write_digit(&mut text, digit);
+            }
+        }
+        text
+    }
+
+    pub fn sqrt(&self, scale: usize) -> Result<Number, &'static str> {
+        if self.negative {
+            return Err("Square root of a negative number");
+        }
+
+        // floor(sqrt(A / 10^sa) * 10^scale) == floor(sqrt(A * 10^(2 * scale - sa)))
+        let scale = scale.max(self.scale);
+        let radicand = shift(&self.digits, 2 * scale - self.scale);
+        Ok(Self::from_parts(false, isqrt(&radicand), scale))
+    }
+}
+
+impl FromStr for Number {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let (integer_part, fraction_part) = text.split_once('.').unwrap_or((text, ""));
+
+        let mut digits = vec![];
+        for ch in integer_part.chars().chain(fraction_part.chars()).rev() {
+            match ch.to_digit(10) {
+                Some(digit) => digits.push(digit as u8),
+                None => return Err(format!("Invalid digit in number: {}", ch)),
+            }
+        }
+
+        Ok(Self::from_parts(negative, digits, fraction_part.len()))
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        let mut text = String::new();
+        if self.negative {
+            text.push('-');
+        }
+        // Like bc, there's no leading zero for numbers smaller than one: `.5`.
+        for idx in (self.scale..self.digits.len()).rev() {
+            text.push(digit_char(self.digits[idx]));
+        }
+        if self.scale > 0 {
+            text.push('.');
+            for idx in (0..self.scale).rev() {
+                text.push(digit_char(*self.digits.get(idx).unwrap_or(&0)));
+            }
+        }
+
+        f.write_str(&text)
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Number {}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Number {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (negative, _) => {
+                let scale = self.scale.max(other.scale);
+                let a = shift(&self.digits, scale - self.scale);
+                let b = shift(&other.digits, scale - other.scale);
+                let ordering = cmp_digits(&a, &b);
+                if negative {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            }
+        }
+    }
+}
+
+fn digit_char(digit: u8) -> char {
+    (b'0' + digit) as char
+}
+
+// The tokenizer only lets `0-9` and `A-Z` through.
+fn digit_value(ch: char) -> usize {
+    ch.to_digit(36).unwrap_or(0) as usize
+}
+
+fn trim(digits: &mut Vec<u8>) {
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+}
+
+/// Multiplies by 10^places.
+fn shift(digits: &[u8], places: usize) -> Vec<u8> {
+    if digits.is_empty() {
+        return vec![];
+    }
+    let mut shifted = vec![0; places];
+    shifted.extend_from_slice(digits);
+    shifted
+}
+
+fn cmp_digits(a: &[u8], b: &[u8]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_digits(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0;
+    for idx in 0..a.len().max(b.len()) {
+        let sum = a.get(idx).unwrap_or(&0) + b.get(idx).unwrap_or(&0) + carry;
+        result.push(sum % 10);
+        carry = sum / 10;
+    }
+    if carry > 0 {
+        result.push(carry);
+    }
+    result
+}
+
+// Expects a >= b.
+fn sub_digits(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+    for (idx, &digit) in a.iter().enumerate() {
+        let subtrahend = b.get(idx).unwrap_or(&0) + borrow;
+        if digit < subtrahend {
+            result.push(digit + 10 - subtrahend);
+            borrow = 1;
+        } else {
+            result.push(digit - subtrahend);
+            borrow = 0;
+        }
+    }
+    trim(&mut result);
+    result
+}
+
+fn mul_digits(a: &[u8], b: &[u8]) -> Vec<u8> {
+    if a.is_empty() || b.is_empty() {
+        return vec![];
+    }
+
+    let mut columns = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        if x == 0 {
+            continue;
+        }
+        let mut carry = 0;
+        for (j, &y) in b.iter().enumerate() {
+            let current = columns[i + j] + x as u32 * y as u32 + carry;
+            columns[i + j] = current % 10;
+            carry = current / 10;
+        }
+        let mut idx = i + b.len();
+        while carry > 0 {
+            let current = columns[idx] + carry;
+            columns[idx] = current % 10;
+            carry = current / 10;
+            idx += 1;
+        }
+    }
+
+    let mut result: Vec<u8> = columns.into_iter().map(|digit| digit as u8).collect();
+    trim(&mut result);
+    result
+}
+
+// Plain long division, the way it's done on paper. Expects a non-zero b.
+fn divmod_digits(a: &[u8], b: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut quotient = vec![0; a.len()];
+    let mut remainder = vec![];
+    for idx in (0..a.len()).rev() {
+        remainder.insert(0, a[idx]);
+        trim(&mut remainder);
+
+        let mut count = 0;
+        while cmp_digits(&remainder, b) != Ordering::Less {
+            remainder = sub_digits(&remainder, b);
+            count += 1;
+        }
+        quotient[idx] = count;
+    }
+    trim(&mut quotient);
+    (quotient, remainder)
+}
+
+// Newton's method on integers, starting from a power of ten that is surely
+// larger than the root. The iteration goes down until it stops decreasing.
+fn isqrt(n: &[u8]) -> Vec<u8> {
+    if n.is_empty() {
+        return vec![];
+    }
+
+    let mut x = shift(&[1], n.len() / 2 + 1);
+    loop {
+        let (quotient, _) = divmod_digits(n, &x);
+        let (next, _) = divmod_digits(&add_digits(&x, &quotient), &[2]);
+        if cmp_digits(&next, &x) != Ordering::Less {
+            return x;
+        }
+        x = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Number;
+
+    fn num(text: &str) -> Number {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn add_and_sub_signs() {
+        assert_eq!(num("5").add(&num("-3")).to_string(), "2");
+        assert_eq!(num("3").add(&num("-5")).to_string(), "-2");
+        assert_eq!(num("-3").add(&num("-5")).to_string(), "-8");
+        assert_eq!(num("-1.5").add(&num("1.5")).to_string(), "0");
+        assert_eq!(num("3").sub(&num("5")).to_string(), "-2");
+        assert_eq!(num("-3").sub(&num("-5")).to_string(), "2");
+        assert_eq!(num("0.25").sub(&num("1")).to_string(), "-.75");
+    }
+
+    #[test]
+    fn div_truncates() {
+        assert_eq!(num("2").div(&num("3"), 5).unwrap().to_string(), ".66666");
+        assert_eq!(num("-2").div(&num("3"), 5).unwrap().to_string(), "-.66666");
+        assert_eq!(num("7").div(&num("2"), 0).unwrap().to_string(), "3");
+        assert_eq!(num("-7").div(&num("2"), 0).unwrap().to_string(), "-3");
+        assert!(num("1").div(&num("0"), 5).is_err());
+    }
+
+    #[test]
+    fn rem_with_scale() {
+        assert_eq!(num("7").rem(&num("3"), 0).unwrap().to_string(), "1");
+        assert_eq!(num("-7").rem(&num("3"), 0).unwrap().to_string(), "-1");
+        // 5 - (5 / 3 to two places) * 3 == 5 - 1.66 * 3
+        assert_eq!(num("5").rem(&num("3"), 2).unwrap().to_string(), ".02");
+        assert_eq!(num("5.5").rem(&num("2"), 0).unwrap().to_string(), "1.5");
+    }
+
+    #[test]
+    fn pow_negative_exponent() {
+        assert_eq!(num("2").pow(&num("-2"), 5).unwrap().to_string(), ".25000");
+        assert_eq!(num("3").pow(&num("-1"), 4).unwrap().to_string(), ".3333");
+        assert_eq!(num("-2").pow(&num("-3"), 3).unwrap().to_string(), "-.125");
+        assert_eq!(num("2").pow(&num("10"), 0).unwrap().to_string(), "1024");
+        assert_eq!(num("1.5").pow(&num("2"), 0).unwrap().to_string(), "2.2");
+        assert!(num("0").pow(&num("-1"), 0).is_err());
+    }
+
+    #[test]
+    fn sqrt_scale() {
+        assert_eq!(num("2").sqrt(0).unwrap().to_string(), "1");
+        assert_eq!(num("2").sqrt(5).unwrap().to_string(), "1.41421");
+        // The scale is at least the scale of the number.
+        assert_eq!(num("2.0000").sqrt(0).unwrap().to_string(), "1.4142");
+        assert_eq!(num("16").sqrt(2).unwrap().to_string(), "4.00");
+        assert!(num("-4").sqrt(0).is_err());
+    }
+
+    #[test]
+    fn radix_round_trips() {
+        for (base, text) in [(16, "FF"), (2, "101101"), (8, "-777"), (16, "A.8")] {
+            let (negative, digits) = match text.strip_prefix('-') {
+                Some(rest) => (true, rest),
+                None => (false, text),
+            };
+            let mut number = Number::from_str_radix(digits, base);
+            if negative {
+                number = number.negated();
+            }
+            assert_eq!(number.to_string_radix(base), text);
+        }
+        assert_eq!(Number::from_str_radix("FF", 16).to_string(), "255");
+        assert_eq!(num("25").to_string_radix(20), " 01 05");
+        assert_eq!(Number::from_str_radix("19", 8).to_string(), "15");
+    }
+}
@@ -0,0 +1,186 @@
+// The command line, GNU bc's options. `BC_ENV_ARGS` is read as if its
+// words came before the real arguments.
+
+pub const USAGE: &str = "\
+usage: bc [options] [file ...]
+  -e, --expression=EXPR  run EXPR
+  -f, --file=FILE        run the code in FILE
+  -h, --help             print this usage and exit
+  -i, --interactive      force interactive mode
+  -l, --mathlib          use the predefined math routines
+  -q, --quiet            don't print the banner
+  -s, --standard         non-standard bc constructs are errors
+  -w, --warn             warn about non-standard bc constructs
+  -v, --version          print version information and exit
+      --load=FILE        start from a session saved with --save or :save
+      --save=FILE        save the session to FILE when bc exits
+
+Expressions, files given with -f and file arguments run in order. After
+them the standard input is read, unless there were -e or -f options on the
+command line.
+
+A statement goes on to the next line after a backslash, and also after an
+operator or a comma or inside parentheses and brackets. That last part
+isn't POSIX bc, -s and -w report it.";
+
+// What to do with the parts of the language POSIX bc doesn't have.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Extensions {
+    Allowed,
+    Warned,
+    Rejected,
+}
+
+#[derive(Debug)]
+pub enum Source {
+    Expression(String),
+    File(String),
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub mathlib: bool,
+    pub quiet: bool,
+    pub interactive: bool, // Forced with -i, otherwise it depends on the terminal.
+    pub extensions: Extensions,
+    pub help: bool,
+    pub version: bool,
+    pub sources: Vec<Source>,
+    pub read_stdin: bool,
+    pub load: Option<String>,
+    pub save: Option<String>,
+}
+
+impl Options {
+    pub fn parse(env_args: Option<String>, args: Vec<String>) -> Result<Options, String> {
+        let mut options = Options {
+            mathlib: false,
+            quiet: false,
+            interactive: false,
+            extensions: Extensions::Allowed,
+            help: false,
+            version: false,
+            sources: vec![],
+            read_stdin: true,
+            load: None,
+            save: None,
+        };
+
+        if let Some(env_args) = env_args {
+            let env_args: Vec<String> = env_args.split_whitespace().map(String::from).collect();
+            options.parse_args(&env_args)?;
+            // Only -e and -f on the real command line mean there's nothing to read.
+            options.read_stdin = true;
+        }
+        options.parse_args(&args)?;
+
+        if options.interactive {
+            options.read_stdin = true;
+        }
+        Ok(options)
+    }
+
+    fn parse_args(&mut self, args: &[String]) -> Result<(), String> {
+        let mut args = args.iter();
+        let mut only_files = false;
+
+        while let Some(arg) = args.next() {
+            if only_files || arg == "-" || !arg.starts_with('-') {
+                self.sources.push(Source::File(arg.clone()));
+            } else if arg == "--" {
+                only_files = true;
+            } else if let Some(long) = arg.strip_prefix("--") {
+                // --file=FILE or --file FILE
+                let (name, value) = match long.split_once('=') {
+                    Some((name, value)) => (name, Some(value.to_string())),
+                    None => (long, None),
+                };
+                let mut value = || match value.clone() {
+                    Some(value) => Ok(value),
+                    None => args
+                        .next()
+                        .cloned()
+                        .ok_or(format!("Option `--{}` needs an argument", name)),
+                };
+
+                match name {
+                    "expression" => self.add_expression(value()?),
+                    "file" => self.add_file(value()?),
+                    "load" => self.load = Some(value()?),
+                    "save" => self.save = Some(value()?),
+                    _ => self.set_flag(name, arg)?,
+                }
+            } else {
+                // Short options can be grouped, -lq. The ones that take an
+                // argument take the rest of the group or the next one: -e1+1.
+                let flags: Vec<char> = arg.chars().skip(1).collect();
+                for (idx, flag) in flags.iter().enumerate() {
+                    match flag {
+                        'e' | 'f' => {
+                            let rest: String = flags[idx + 1..].iter().collect();
+                            let value = if rest.is_empty() {
+                                args.next()
+                                    .cloned()
+                                    .ok_or(format!("Option `-{}` needs an argument", flag))?
+                            } else {
+                                rest
+                            };
+                            if *flag == 'e' {
+                                self.add_expression(value);
+                            } else {
+                                self.add_file(value);
+                            }
+                            break;
+                        }
+                        _ => self.set_flag(&short_name(*flag), &format!("-{}", flag))?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_expression(&mut self, expression: String) {
+        self.sources.push(Source::Expression(expression));
+        self.read_stdin = false;
+    }
+
+    fn add_file(&mut self, path: String) {
+        self.sources.push(Source::File(path));
+        self.read_stdin = false;
+    }
+
+    // `shown` is the option as it was written, for the error.
+    fn set_flag(&mut self, name: &str, shown: &str) -> Result<(), String> {
+        match name {
+            "help" => self.help = true,
+            "interactive" => self.interactive = true,
+            "mathlib" => self.mathlib = true,
+            "quiet" => self.quiet = true,
+            "standard" => self.extensions = Extensions::Rejected,
+            "warn" => {
+                // -s wins, whatever order they come in.
+                if self.extensions != Extensions::Rejected {
+                    self.extensions = Extensions::Warned;
+                }
+            }
+            "version" => self.version = true,
+            _ => return Err(format!("Unknown option `{}`", shown)),
+        }
+        Ok(())
+    }
+}
+
+fn short_name(flag: char) -> String {
+    let name = match flag {
+        'h' => "help",
+        'i' => "interactive",
+        'l' => "mathlib",
+        'q' => "quiet",
+        's' => "standard",
+        'w' => "warn",
+        'v' => "version",
+        _ => return format!("-{}", flag),
+    };
+    name.to_string()
+}
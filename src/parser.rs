@@ -1,26 +1,134 @@
 // Great resource for pratt parsers: https://www.oilshell.org/blog/2017/03/31.html
 
-use crate::tokenizer::Token;
+use crate::error::Error;
+use crate::tokenizer::{Span, Token, TokenKind};
 use std::fmt;
 
-pub enum AstNode<'source> {
+// How deep statements and expressions can nest. Parsing and compiling recurse
+// on the tree, so this keeps `((((...` from overflowing the stack.
+const MAX_DEPTH: usize = 10000;
+
+pub struct AstNode<'source> {
+    pub kind: NodeKind<'source>,
+    pub span: Span,
+}
+
+pub enum NodeKind<'source> {
     Ident(&'source str),
-    Number(f64),
-    Op(Token<'source>, Vec<AstNode<'source>>),
+    Element(&'source str, Box<AstNode<'source>>), // name[index]
+    Array(&'source str),                          // name[], a whole array passed to a function.
+    Number(&'source str), // Converted when it runs, `ibase` can change in the meantime.
+    Op(TokenKind<'source>, Vec<AstNode<'source>>),
+    Paren(Box<AstNode<'source>>), // Kept so `(x = 5)` prints, a bare assignment doesn't.
+    Postfix(TokenKind<'source>, Box<AstNode<'source>>), // x++ and x--, prefix ones are `Op`s.
+    Define(
+        &'source str,
+        Vec<Param<'source>>, // Parameters
+        Vec<Param<'source>>, // `auto` variables
+        Vec<AstNode<'source>>,
+    ),
+    Return(Option<Box<AstNode<'source>>>),
+    Block(Vec<AstNode<'source>>),
+    If(Box<AstNode<'source>>, Box<AstNode<'source>>, Option<Box<AstNode<'source>>>),
+    While(Box<AstNode<'source>>, Box<AstNode<'source>>),
+    For(
+        Option<Box<AstNode<'source>>>, // Initialization
+        Option<Box<AstNode<'source>>>, // Condition, the loop goes on forever without it.
+        Option<Box<AstNode<'source>>>, // Step
+        Box<AstNode<'source>>,
+    ),
+    Break,
+    Continue,
+    Halt,
+    Str(&'source str),              // A bare string prints itself, as it is.
+    Print(Vec<AstNode<'source>>), // Strings and expressions, in order.
+}
+
+#[derive(Clone, Copy)]
+pub struct Param<'source> {
+    pub name: &'source str,
+    pub is_array: bool,
+}
+
+impl<'source> fmt::Display for Param<'source> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_array {
+            write!(f, "{}[]", self.name)
+        } else {
+            write!(f, "{}", self.name)
+        }
+    }
+}
+
+impl<'source> AstNode<'source> {
+    fn new(kind: NodeKind<'source>, span: Span) -> Self {
+        Self { kind, span }
+    }
 }
 
 impl<'source> fmt::Display for AstNode<'source> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            AstNode::Ident(ident) => write!(f, "{}", ident),
-            AstNode::Number(number) => write!(f, "{}", number),
-            AstNode::Op(op, children) => {
+        match &self.kind {
+            NodeKind::Ident(ident) => write!(f, "{}", ident),
+            NodeKind::Element(name, index) => write!(f, "{}[{}]", name, index),
+            NodeKind::Array(name) => write!(f, "{}[]", name),
+            NodeKind::Number(number) => write!(f, "{}", number),
+            NodeKind::Op(op, children) => {
                 write!(f, "({:?}", op)?;
                 for node in children {
                     write!(f, " {}", node)?
                 }
                 write!(f, ")")
             }
+            NodeKind::Paren(inner) => write!(f, "{}", inner),
+            NodeKind::Postfix(op, operand) => write!(f, "(Postfix{:?} {})", op, operand),
+            NodeKind::Define(name, params, autos, body) => {
+                let params: Vec<String> = params.iter().map(|param| param.to_string()).collect();
+                write!(f, "(define {} ({})", name, params.join(" "))?;
+                if !autos.is_empty() {
+                    let autos: Vec<String> = autos.iter().map(|auto| auto.to_string()).collect();
+                    write!(f, " (auto {})", autos.join(" "))?;
+                }
+                for statement in body {
+                    write!(f, " {}", statement)?
+                }
+                write!(f, ")")
+            }
+            NodeKind::Return(Some(value)) => write!(f, "(return {})", value),
+            NodeKind::Return(None) => write!(f, "(return)"),
+            NodeKind::Block(statements) => {
+                write!(f, "(block")?;
+                for statement in statements {
+                    write!(f, " {}", statement)?
+                }
+                write!(f, ")")
+            }
+            NodeKind::If(condition, then, Some(otherwise)) => {
+                write!(f, "(if {} {} {})", condition, then, otherwise)
+            }
+            NodeKind::If(condition, then, None) => write!(f, "(if {} {})", condition, then),
+            NodeKind::While(condition, body) => write!(f, "(while {} {})", condition, body),
+            NodeKind::For(init, condition, step, body) => {
+                write!(f, "(for")?;
+                for part in [init, condition, step] {
+                    match part {
+                        Some(part) => write!(f, " {}", part)?,
+                        None => write!(f, " ()")?,
+                    }
+                }
+                write!(f, " {})", body)
+            }
+            NodeKind::Break => write!(f, "(break)"),
+            NodeKind::Continue => write!(f, "(continue)"),
+            NodeKind::Halt => write!(f, "(halt)"),
+            NodeKind::Str(text) => write!(f, "\"{}\"", text),
+            NodeKind::Print(items) => {
+                write!(f, "(print")?;
+                for item in items {
+                    write!(f, " {}", item)?
+                }
+                write!(f, ")")
+            }
         }
     }
 }
@@ -28,7 +136,10 @@ impl<'source> fmt::Display for AstNode<'source> {
 pub struct Parser<'source> {
     tokens: Vec<Token<'source>>,
     current_idx: usize,
-    had_error: bool,
+    brace_depth: usize,
+    in_function: bool,
+    depth: usize, // How deep the node being parsed is.
+    errors: Vec<Error>,
 }
 
 impl<'source> Parser<'source> {
@@ -36,163 +147,634 @@ impl<'source> Parser<'source> {
         Self {
             tokens,
             current_idx: 0,
-            had_error: false,
+            brace_depth: 0,
+            in_function: false,
+            depth: 0,
+            errors: Vec::new(),
         }
     }
 
-    pub fn parse(mut self) -> Vec<AstNode<'source>> {
+    // Parsing goes on after a syntax error, so every error in the source is
+    // reported at once. The statements that parsed can still run.
+    pub fn parse(mut self) -> (Vec<AstNode<'source>>, Vec<Error>) {
         let mut statements = Vec::new();
         loop {
-            match self.get_current_token() {
-                Token::EOF => return statements,
-                Token::StatementEnd => self.advance(),
-                _ => statements.push(self.parse_expr(0)),
+            match self.current_kind() {
+                // The tokenizer ends the tokens at a `quit`.
+                TokenKind::EOF | TokenKind::Quit => break,
+                TokenKind::StatementEnd => self.advance(),
+                _ => match self.parse_statement() {
+                    Ok(statement) => statements.push(statement),
+                    Err(error) => {
+                        self.errors.push(error);
+                        self.synchronize(0);
+                    }
+                },
+            }
+        }
+
+        (statements, self.errors)
+    }
+
+    // Skips the rest of the statement that had the error, the one that is
+    // `depth` braces deep. Stops before the `}` closing that block.
+    fn synchronize(&mut self, depth: usize) {
+        loop {
+            match self.current_kind() {
+                TokenKind::EOF => return,
+                TokenKind::StatementEnd if self.brace_depth <= depth => {
+                    self.advance();
+                    return;
+                }
+                TokenKind::RightBrace if self.brace_depth <= depth && depth > 0 => return,
+                _ => self.advance(),
+            }
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<AstNode<'source>, Error> {
+        let depth = self.depth;
+        let result = self.parse_nested_statement();
+        self.depth = depth;
+        result
+    }
+
+    fn parse_nested_statement(&mut self) -> Result<AstNode<'source>, Error> {
+        self.nest()?;
+        match self.current_kind() {
+            TokenKind::Define => self.parse_define(),
+            TokenKind::Return => self.parse_return(),
+            TokenKind::If => self.parse_if(),
+            TokenKind::While => self.parse_while(),
+            TokenKind::For => self.parse_for(),
+            TokenKind::Break | TokenKind::Continue | TokenKind::Halt => {
+                let tok = self.current_token();
+                self.advance();
+                let kind = match tok.kind {
+                    TokenKind::Break => NodeKind::Break,
+                    TokenKind::Continue => NodeKind::Continue,
+                    _ => NodeKind::Halt,
+                };
+                Ok(AstNode::new(kind, tok.span))
+            }
+            TokenKind::Str(text) => {
+                let tok = self.current_token();
+                self.advance();
+                match self.current_kind() {
+                    TokenKind::StatementEnd
+                    | TokenKind::EOF
+                    | TokenKind::RightBrace
+                    | TokenKind::Else => Ok(AstNode::new(NodeKind::Str(text), tok.span)),
+                    _ => Err(self.error("Strings can't be used in expressions!")),
+                }
+            }
+            TokenKind::Print => self.parse_print(),
+            TokenKind::Auto => Err(self.error("`auto` has to be the first thing in a function body!")),
+            TokenKind::LeftBrace => {
+                let start = self.current_span();
+                self.advance();
+                let statements = self.parse_block()?;
+                Ok(AstNode::new(NodeKind::Block(statements), start.to(self.previous_span())))
+            }
+            _ => self.parse_expr(0),
+        }
+    }
+
+    // print item, item, ...
+    fn parse_print(&mut self) -> Result<AstNode<'source>, Error> {
+        let start = self.current_span();
+        self.advance();
+
+        let mut items = vec![];
+        loop {
+            let item = match self.current_kind() {
+                TokenKind::Str(text) => {
+                    let span = self.current_span();
+                    self.advance();
+                    AstNode::new(NodeKind::Str(text), span)
+                }
+                _ => self.parse_expr(0)?,
+            };
+            items.push(item);
+
+            if self.current_kind() != TokenKind::ArgSeperator {
+                break;
+            }
+            self.advance();
+        }
+        Ok(AstNode::new(NodeKind::Print(items), start.to(self.previous_span())))
+    }
+
+    // The statement after `if (...)`, `while (...)`, `else` etc. can be on the next line.
+    fn parse_body(&mut self) -> Result<AstNode<'source>, Error> {
+        while self.current_kind() == TokenKind::StatementEnd {
+            self.advance();
+        }
+        self.parse_statement()
+    }
+
+    // ( expression )
+    fn parse_condition(&mut self) -> Result<AstNode<'source>, Error> {
+        self.expect(TokenKind::LeftParen)?;
+        self.advance();
+        let condition = self.parse_expr(0)?;
+        self.expect(TokenKind::RightParen)?;
+        self.advance();
+        Ok(condition)
+    }
+
+    fn parse_if(&mut self) -> Result<AstNode<'source>, Error> {
+        let start = self.current_span();
+        self.advance();
+
+        let condition = self.parse_condition()?;
+        let then = self.parse_body()?;
+
+        // The `else` can be on the next line too, so look past the line ends,
+        // but leave them alone if there's no `else`.
+        let before_else = self.current_idx;
+        while self.current_kind() == TokenKind::StatementEnd {
+            self.advance();
+        }
+        let otherwise = if self.current_kind() == TokenKind::Else {
+            self.advance();
+            Some(Box::new(self.parse_body()?))
+        } else {
+            self.current_idx = before_else;
+            None
+        };
+
+        let kind = NodeKind::If(Box::new(condition), Box::new(then), otherwise);
+        Ok(AstNode::new(kind, start.to(self.previous_span())))
+    }
+
+    fn parse_while(&mut self) -> Result<AstNode<'source>, Error> {
+        let start = self.current_span();
+        self.advance();
+
+        let condition = self.parse_condition()?;
+        let body = self.parse_body()?;
+
+        let kind = NodeKind::While(Box::new(condition), Box::new(body));
+        Ok(AstNode::new(kind, start.to(self.previous_span())))
+    }
+
+    // for (init; condition; step) body, all three parts are optional.
+    fn parse_for(&mut self) -> Result<AstNode<'source>, Error> {
+        let start = self.current_span();
+        self.advance();
+
+        self.expect(TokenKind::LeftParen)?;
+        self.advance();
+
+        let mut parts = Vec::new();
+        for terminator in [TokenKind::StatementEnd, TokenKind::StatementEnd, TokenKind::RightParen] {
+            if self.current_kind() == terminator {
+                parts.push(None);
+            } else {
+                parts.push(Some(Box::new(self.parse_expr(0)?)));
+            }
+            self.expect(terminator)?;
+            self.advance();
+        }
+        let step = parts.pop().unwrap();
+        let condition = parts.pop().unwrap();
+        let init = parts.pop().unwrap();
+
+        let body = self.parse_body()?;
+
+        let kind = NodeKind::For(init, condition, step, Box::new(body));
+        Ok(AstNode::new(kind, start.to(self.previous_span())))
+    }
+
+    // define name(params) { statements }
+    fn parse_define(&mut self) -> Result<AstNode<'source>, Error> {
+        let start = self.current_span();
+        self.advance();
+
+        let name = match self.current_kind() {
+            TokenKind::Identifier(name) => name,
+            _ => return Err(self.error("Expected a function name after `define`!")),
+        };
+        self.advance();
+
+        self.expect(TokenKind::LeftParen)?;
+        self.advance();
+
+        let params = match self.current_kind() {
+            TokenKind::RightParen => Vec::new(),
+            _ => self.parse_names()?,
+        };
+        self.expect(TokenKind::RightParen)?;
+        self.advance();
+
+        while self.current_kind() == TokenKind::StatementEnd {
+            self.advance();
+        }
+        self.expect(TokenKind::LeftBrace)?;
+        self.advance();
+
+        // `auto` can only be the first thing in the body.
+        while self.current_kind() == TokenKind::StatementEnd {
+            self.advance();
+        }
+        let mut autos = Vec::new();
+        if self.current_kind() == TokenKind::Auto {
+            self.advance();
+            autos = self.parse_names()?;
+        }
+
+        let was_in_function = self.in_function;
+        self.in_function = true;
+        let body = self.parse_block();
+        self.in_function = was_in_function;
+
+        let kind = NodeKind::Define(name, params, autos, body?);
+        Ok(AstNode::new(kind, start.to(self.previous_span())))
+    }
+
+    // name, array[], ... for the parameters and the `auto` list.
+    fn parse_names(&mut self) -> Result<Vec<Param<'source>>, Error> {
+        let mut names = Vec::new();
+        loop {
+            let name = match self.current_kind() {
+                TokenKind::Identifier(name) => name,
+                _ => return Err(self.error("Expected a variable name!")),
+            };
+            let name_span = self.current_span();
+            self.advance();
+
+            let is_array = self.current_kind() == TokenKind::LeftBracket;
+            if is_array {
+                self.advance();
+                self.expect(TokenKind::RightBracket)?;
+                self.advance();
+            }
+            // Their values are only checked when they're assigned, a local
+            // one would start at zero. POSIX doesn't allow it either.
+            if !is_array && matches!(name, "scale" | "ibase" | "obase") {
+                let msg = format!("`{}` can't be a parameter or an `auto` variable!", name);
+                return Err(Error::Syntax(msg, name_span));
+            }
+            names.push(Param { name, is_array });
+
+            if self.current_kind() != TokenKind::ArgSeperator {
+                return Ok(names);
+            }
+            self.advance();
+        }
+    }
+
+    // The statements up to the `}`, the `{` is already consumed. Errors inside
+    // the block are collected, so the block is only returned if all of it parsed.
+    fn parse_block(&mut self) -> Result<Vec<AstNode<'source>>, Error> {
+        let depth = self.brace_depth;
+        let error_count = self.errors.len();
+        let mut body = Vec::new();
+        loop {
+            match self.current_kind() {
+                TokenKind::RightBrace => {
+                    self.advance();
+                    break;
+                }
+                TokenKind::StatementEnd => self.advance(),
+                TokenKind::EOF => return Err(self.error("Missing `}` at the end of the block!")),
+                _ => match self.parse_statement() {
+                    Ok(statement) => body.push(statement),
+                    Err(error) => {
+                        self.errors.push(error);
+                        self.synchronize(depth);
+                    }
+                },
+            }
+        }
+
+        if self.errors.len() > error_count {
+            // Already collected, the caller just has to skip the whole block.
+            return Err(self.errors.pop().unwrap());
+        }
+        Ok(body)
+    }
+
+    fn parse_return(&mut self) -> Result<AstNode<'source>, Error> {
+        if !self.in_function {
+            return Err(self.error("`return` outside of a function!"));
+        }
+        let start = self.current_span();
+        self.advance();
+
+        match self.current_kind() {
+            TokenKind::StatementEnd | TokenKind::RightBrace | TokenKind::EOF => {
+                Ok(AstNode::new(NodeKind::Return(None), start))
+            }
+            _ => {
+                let value = self.parse_expr(0)?;
+                let span = start.to(value.span);
+                Ok(AstNode::new(NodeKind::Return(Some(Box::new(value))), span))
             }
         }
     }
 
     // TODO(ag): There are `self.advance()`s all over the place, so maybe
     // use .peek and .next like normal people. This can break very easily.
-    fn parse_expr(&mut self, minimum_precedence: u8) -> AstNode<'source> {
-        let tok = self.get_current_token();
+    fn parse_expr(&mut self, minimum_precedence: u8) -> Result<AstNode<'source>, Error> {
+        let depth = self.depth;
+        let result = self.parse_nested_expr(minimum_precedence);
+        self.depth = depth;
+        result
+    }
 
-        let mut left = match tok {
-            Token::Number(number_str, _) => {
+    fn parse_nested_expr(&mut self, minimum_precedence: u8) -> Result<AstNode<'source>, Error> {
+        self.nest()?;
+        let tok = self.current_token();
+
+        let mut left = match tok.kind {
+            TokenKind::Number(number_str) => {
                 self.advance();
-                let number = number_str.parse().unwrap();
-                AstNode::Number(number)
+                AstNode::new(NodeKind::Number(number_str), tok.span)
             }
-            Token::Identifier(ident_str, _) => {
+            TokenKind::Identifier(ident_str) => {
                 self.advance();
 
-                if self.get_current_token() == Token::LeftParen {
-                    let args = self.parse_args();
-                    self.expect(Token::RightParen);
+                if self.current_kind() == TokenKind::LeftParen {
+                    let args = self.parse_args()?;
+                    self.expect(TokenKind::RightParen)?;
+                    self.advance();
+
+                    let span = tok.span.to(self.previous_span());
+                    AstNode::new(NodeKind::Op(TokenKind::FnCall(ident_str), args), span)
+                } else if self.current_kind() == TokenKind::LeftBracket {
+                    self.advance();
+                    let kind = if self.current_kind() == TokenKind::RightBracket {
+                        NodeKind::Array(ident_str)
+                    } else {
+                        NodeKind::Element(ident_str, Box::new(self.parse_expr(0)?))
+                    };
+                    self.expect(TokenKind::RightBracket)?;
                     self.advance();
 
-                    AstNode::Op(Token::FnCall(ident_str), args)
+                    AstNode::new(kind, tok.span.to(self.previous_span()))
                 } else {
-                    AstNode::Ident(ident_str)
+                    AstNode::new(NodeKind::Ident(ident_str), tok.span)
                 }
             }
-            Token::LeftParen => {
+            TokenKind::LeftParen => {
+                self.advance();
+                let inner = self.parse_expr(0)?;
+                self.expect(TokenKind::RightParen)?;
                 self.advance();
-                let l = self.parse_expr(0);
-                self.expect(Token::RightParen);
+                let span = tok.span.to(self.previous_span());
+                AstNode::new(NodeKind::Paren(Box::new(inner)), span)
+            }
+            TokenKind::Plus | TokenKind::Minus | TokenKind::Not => {
                 self.advance();
-                l
+                let right_prec = prefix_precedence(tok.kind);
+                let right = self.parse_expr(right_prec)?;
+                let span = tok.span.to(right.span);
+                AstNode::new(NodeKind::Op(tok.kind, vec![right]), span)
             }
-            Token::Plus | Token::Minus => {
+            TokenKind::Increment | TokenKind::Decrement => {
                 self.advance();
-                let right_prec = prefix_precedence(tok);
-                let right = self.parse_expr(right_prec);
-                AstNode::Op(tok, vec![right])
+                let operand = self.parse_expr(prefix_precedence(tok.kind))?;
+                if !is_lvalue(&operand) {
+                    return Err(Error::Syntax(step_error(tok.kind), operand.span));
+                }
+                let span = tok.span.to(operand.span);
+                AstNode::new(NodeKind::Op(tok.kind, vec![operand]), span)
             }
             _ => {
-                panic!("bad tok: {:?}", tok); // TODO proper error handling.
+                return Err(self.error(&format!("Unexpected token: {:?}", tok.kind)));
             }
         };
 
+        let mut compared = false;
         loop {
-            let tok = self.get_current_token();
+            // Every operator makes the tree one deeper, `1+1+1...` too.
+            self.nest()?;
+            let tok = self.current_kind();
+
+            if let TokenKind::Increment | TokenKind::Decrement = tok {
+                if postfix_precedence(tok) < minimum_precedence {
+                    break;
+                }
+                if !is_lvalue(&left) {
+                    return Err(Error::Syntax(step_error(tok), left.span));
+                }
+                self.advance();
+                let span = left.span.to(self.previous_span());
+                left = AstNode::new(NodeKind::Postfix(tok, Box::new(left)), span);
+                continue;
+            }
 
             let op = match tok {
-                Token::Plus
-                | Token::Minus
-                | Token::Star
-                | Token::Power
-                | Token::Slash
-                | Token::Percent
-                | Token::Equals
-                | Token::PlusEquals
-                | Token::MinusEquals
-                | Token::StarEquals
-                | Token::SlashEquals => tok,
-                Token::EOF | Token::StatementEnd => {
+                TokenKind::Plus
+                | TokenKind::Minus
+                | TokenKind::Star
+                | TokenKind::Power
+                | TokenKind::Slash
+                | TokenKind::Percent
+                | TokenKind::Equals
+                | TokenKind::PlusEquals
+                | TokenKind::MinusEquals
+                | TokenKind::StarEquals
+                | TokenKind::SlashEquals
+                | TokenKind::PercentEquals
+                | TokenKind::PowerEquals
+                | TokenKind::Less
+                | TokenKind::LessEquals
+                | TokenKind::Greater
+                | TokenKind::GreaterEquals
+                | TokenKind::EqualsEquals
+                | TokenKind::NotEquals
+                | TokenKind::And
+                | TokenKind::Or => tok,
+                TokenKind::EOF | TokenKind::StatementEnd => {
                     break;
                 }
-                Token::RightParen => {
+                TokenKind::RightParen
+                | TokenKind::RightBrace
+                | TokenKind::RightBracket
+                | TokenKind::Else => {
                     break;
                 }
-                Token::ArgSeperator => {
+                TokenKind::ArgSeperator => {
                     break;
                 }
                 _ => {
-                    panic!("bad op: {:?}", tok); // TODO proper error handling.
+                    return Err(self.error(&format!("Expected an operator, found {:?}", tok)));
                 }
             };
             let (left_prec, right_prec) = infix_precedence(op);
             if left_prec < minimum_precedence {
                 break;
             }
+            if is_relational_op(op) {
+                // They don't associate, `a < b < c` needs parentheses.
+                if compared {
+                    return Err(self.error("Comparisons can't be chained, use parentheses!"));
+                }
+                compared = true;
+            }
+            if is_assignment_op(op) && !is_lvalue(&left) {
+                let msg = String::from("Only variables and array elements can be assigned to!");
+                return Err(Error::Syntax(msg, left.span));
+            }
 
             self.advance();
-            let right = self.parse_expr(right_prec);
+            let right = self.parse_expr(right_prec)?;
 
-            left = AstNode::Op(op, vec![left, right]);
+            let span = left.span.to(right.span);
+            left = AstNode::new(NodeKind::Op(op, vec![left, right]), span);
         }
 
-        left
+        Ok(left)
     }
 
-    fn parse_args(&mut self) -> Vec<AstNode<'source>> {
+    fn parse_args(&mut self) -> Result<Vec<AstNode<'source>>, Error> {
         let mut args = Vec::new();
-        while (self.get_current_token() != Token::RightParen
-            && self.get_current_token() != Token::StatementEnd)
-            || self.get_current_token() == Token::ArgSeperator
-        {
+        self.advance(); // Skip the `(`.
+        if self.current_kind() == TokenKind::RightParen {
+            return Ok(args);
+        }
+
+        loop {
+            args.push(self.parse_expr(0)?);
+            if self.current_kind() != TokenKind::ArgSeperator {
+                break;
+            }
             self.advance();
-            let arg = self.parse_expr(0);
-            args.push(arg);
         }
-        args
+        Ok(args)
+    }
+
+    // The tokenizer always ends the tokens with an `EOF`, so we can keep returning that.
+    fn current_token(&self) -> Token<'source> {
+        self.tokens[self.current_idx.min(self.tokens.len() - 1)]
     }
 
-    fn get_current_token(&self) -> Token<'source> {
-        *self.tokens.get(self.current_idx).unwrap_or(&Token::EOF)
+    fn current_kind(&self) -> TokenKind<'source> {
+        self.current_token().kind
+    }
+
+    fn current_span(&self) -> Span {
+        self.current_token().span
+    }
+
+    fn previous_span(&self) -> Span {
+        self.tokens[self.current_idx.saturating_sub(1).min(self.tokens.len() - 1)].span
     }
 
     fn advance(&mut self) {
+        match self.current_kind() {
+            TokenKind::LeftBrace => self.brace_depth += 1,
+            TokenKind::RightBrace => self.brace_depth = self.brace_depth.saturating_sub(1),
+            _ => {}
+        }
         self.current_idx += 1;
     }
 
-    fn expect(&mut self, token: Token) {
-        let current_token = self.get_current_token();
-        if token != current_token {
-            self.error("Didn't see expected token!");
+    fn expect(&mut self, kind: TokenKind) -> Result<(), Error> {
+        let current_kind = self.current_kind();
+        if kind != current_kind {
+            return Err(self.error(&format!("Expected {:?}, found {:?}", kind, current_kind)));
         }
+        Ok(())
     }
 
-    fn error(&mut self, msg: &str) {
-        if !self.had_error {
-            self.had_error = true;
-            println!("Syntax error: {}", msg);
+    // A syntax error pointing at the current token.
+    fn nest(&mut self) -> Result<(), Error> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.error("Nested too deeply!"));
         }
+        Ok(())
+    }
+
+    fn error(&self, msg: &str) -> Error {
+        Error::Syntax(msg.to_string(), self.current_span())
     }
 }
 
-fn prefix_precedence(tok: Token) -> u8 {
+// Binding powers, higher binds tighter. They follow GNU bc, from the loosest
+// to the tightest:
+//
+//   ||                    left associative
+//   &&                    left associative
+//   !                     prefix
+//   < <= > >= == !=       not associative
+//   = += -= *= /= %= ^=   right associative
+//   + -                   left associative
+//   * / %                 left associative
+//   ^                     right associative
+//   unary - and +         prefix, so -2^2 is (-2)^2
+//   ++ --                 prefix and postfix
+//
+// POSIX bc doesn't have `||`, `&&` and `!`. The relational operators bind
+// looser than assignment (`a = 1 < 2` is `(a = 1) < 2`) and `!` looser than
+// those (`!a < b` is `!(a < b)`).
+
+// Only called with the tokens `parse_expr` accepts as prefix operators.
+fn prefix_precedence(tok: TokenKind) -> u8 {
     match tok {
-        Token::Plus | Token::Minus => 5,
-        _ => {
-            panic!("bad op: {:?}", tok); // TODO proper error handling.
-        }
+        TokenKind::Not => 6,
+        TokenKind::Plus | TokenKind::Minus => 19,
+        TokenKind::Increment | TokenKind::Decrement => 20,
+        _ => unreachable!("bad prefix op: {:?}", tok),
     }
 }
 
-fn infix_precedence(tok: Token) -> (u8, u8) {
+fn postfix_precedence(tok: TokenKind) -> u8 {
     match tok {
-        Token::Equals
-        | Token::PlusEquals
-        | Token::MinusEquals
-        | Token::StarEquals
-        | Token::SlashEquals => (1, 0),
-        Token::Percent => (1, 2),
-        Token::Plus | Token::Minus => (3, 4),
-        Token::Star | Token::Slash => (5, 6),
-        Token::Power => (6, 7),
-        _ => {
-            panic!("bad op: {:?}", tok); // TODO proper error handling.
-        }
+        TokenKind::Increment | TokenKind::Decrement => 21,
+        _ => unreachable!("bad postfix op: {:?}", tok),
+    }
+}
+
+fn step_error(tok: TokenKind) -> String {
+    let symbol = if tok == TokenKind::Increment { "++" } else { "--" };
+    format!("`{}` needs a variable or an array element!", symbol)
+}
+
+fn is_relational_op(tok: TokenKind) -> bool {
+    matches!(
+        tok,
+        TokenKind::Less
+            | TokenKind::LessEquals
+            | TokenKind::Greater
+            | TokenKind::GreaterEquals
+            | TokenKind::EqualsEquals
+            | TokenKind::NotEquals
+    )
+}
+
+pub fn is_assignment_op(tok: TokenKind) -> bool {
+    matches!(
+        tok,
+        TokenKind::Equals
+            | TokenKind::PlusEquals
+            | TokenKind::MinusEquals
+            | TokenKind::StarEquals
+            | TokenKind::SlashEquals
+            | TokenKind::PercentEquals
+            | TokenKind::PowerEquals
+    )
+}
+
+// What can be on the left of `=`, or next to `++`.
+fn is_lvalue(node: &AstNode) -> bool {
+    matches!(node.kind, NodeKind::Ident(_) | NodeKind::Element(..))
+}
+
+// Only called with the tokens `parse_expr` accepts as infix operators.
+fn infix_precedence(tok: TokenKind) -> (u8, u8) {
+    match tok {
+        TokenKind::Or => (2, 3),
+        TokenKind::And => (4, 5),
+        op if is_relational_op(op) => (7, 8),
+        op if is_assignment_op(op) => (10, 9),
+        TokenKind::Plus | TokenKind::Minus => (13, 14),
+        TokenKind::Star | TokenKind::Slash | TokenKind::Percent => (15, 16),
+        TokenKind::Power => (18, 17),
+        _ => unreachable!("bad infix op: {:?}", tok),
     }
 }
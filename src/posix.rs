@@ -0,0 +1,142 @@
+// Finds what POSIX bc doesn't have, for `-s` and `-w`. All of these can be
+// seen in the tokens, so this runs before the parser.
+
+use crate::tokenizer::{Span, Token, TokenKind};
+
+// The only names POSIX allows that are longer than a letter.
+const LONG_NAMES: [&str; 5] = ["scale", "ibase", "obase", "sqrt", "length"];
+
+// What a `(` opened. POSIX only has comparisons right inside the condition of
+// an `if` or a `while`, or the middle part of a `for`.
+enum Paren {
+    Condition,
+    For(usize), // How many `;`s were seen.
+    Other,
+}
+
+pub fn extensions(source: &str, tokens: &[Token]) -> Vec<(String, Span)> {
+    let mut found = vec![];
+    let mut previous_end = 0;
+    let mut parens = vec![];
+
+    for (idx, token) in tokens.iter().enumerate() {
+        // Only spaces and comments are between the tokens.
+        if let Some(span) = line_comment(source, previous_end, token.span) {
+            found.push((String::from("`#` comments"), span));
+        }
+        // The tokenizer leaves out the newline after an operator or inside
+        // parentheses, in POSIX bc that newline ends the statement.
+        let previous = idx.checked_sub(1).map(|idx| tokens[idx]);
+        if let Some(previous) = previous {
+            if previous.kind != TokenKind::StatementEnd
+                && has_newline(&source[previous_end..token.span.start])
+            {
+                let what = "statements that go on to the next line without a backslash";
+                found.push((String::from(what), previous.span));
+            }
+        }
+        previous_end = token.span.end;
+
+        let previous = previous.map(|token| token.kind);
+        let next = tokens.get(idx + 1).map(|token| token.kind);
+        let what = match token.kind {
+            TokenKind::LeftParen => {
+                parens.push(match previous {
+                    Some(TokenKind::If | TokenKind::While) => Paren::Condition,
+                    Some(TokenKind::For) => Paren::For(0),
+                    _ => Paren::Other,
+                });
+                continue;
+            }
+            TokenKind::RightParen => {
+                parens.pop();
+                continue;
+            }
+            TokenKind::StatementEnd => {
+                if let Some(Paren::For(parts)) = parens.last_mut() {
+                    *parts += 1;
+                }
+                continue;
+            }
+            TokenKind::Less
+            | TokenKind::LessEquals
+            | TokenKind::Greater
+            | TokenKind::GreaterEquals
+            | TokenKind::EqualsEquals
+            | TokenKind::NotEquals => match parens.last() {
+                Some(Paren::Condition | Paren::For(1)) => continue,
+                _ => String::from("comparisons outside of an `if`, `while` or `for` condition"),
+            },
+            TokenKind::Return => match next {
+                Some(TokenKind::LeftParen | TokenKind::StatementEnd | TokenKind::RightBrace) => {
+                    continue
+                }
+                _ => String::from("`return` without parentheses around the value"),
+            },
+            TokenKind::Identifier(name) if name.len() > 1 && !LONG_NAMES.contains(&name) => {
+                format!("names longer than one letter like `{}`", name)
+            }
+            TokenKind::Else => String::from("`else`"),
+            TokenKind::Continue => String::from("`continue`"),
+            TokenKind::Print => String::from("`print`"),
+            TokenKind::Halt => String::from("`halt`"),
+            TokenKind::And => String::from("`&&`"),
+            TokenKind::Or => String::from("`||`"),
+            TokenKind::Not => String::from("`!`"),
+            _ => continue,
+        };
+        found.push((what, token.span));
+    }
+    found
+}
+
+// A `#` comment between `start` and the token after it.
+fn line_comment(source: &str, start: usize, next: Span) -> Option<Span> {
+    let gap = &source[start..next.start];
+    let mut offset = 0;
+    while offset < gap.len() {
+        let rest = &gap[offset..];
+        if rest.starts_with("/*") {
+            offset += rest.find("*/").map_or(rest.len(), |idx| idx + 2);
+        } else if rest.starts_with('#') {
+            let comment_start = start + offset;
+            let length = rest.find('\n').unwrap_or(rest.len());
+            return Some(span_at(source, comment_start, comment_start + length, next));
+        } else {
+            offset += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    None
+}
+
+// A newline between two tokens that isn't in a comment or after a backslash.
+fn has_newline(gap: &str) -> bool {
+    let mut offset = 0;
+    while offset < gap.len() {
+        let rest = &gap[offset..];
+        if rest.starts_with("/*") {
+            offset += rest.find("*/").map_or(rest.len(), |idx| idx + 2);
+        } else if rest.starts_with("\\\n") {
+            offset += 2;
+        } else if rest.starts_with('#') {
+            offset += rest.find('\n').unwrap_or(rest.len());
+        } else if rest.starts_with('\n') {
+            return true;
+        } else {
+            offset += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    false
+}
+
+// The tokenizer knows the lines and columns as it goes, here they have to be
+// counted back from the line of the token after it.
+fn span_at(source: &str, start: usize, end: usize, next: Span) -> Span {
+    let line_start = source[..start].rfind('\n').map_or(0, |idx| idx + 1);
+    Span {
+        start,
+        end,
+        line: next.line - source[start..next.start].matches('\n').count(),
+        column: source[line_start..start].chars().count() + 1,
+    }
+}
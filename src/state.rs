@@ -0,0 +1,97 @@
+// Saving a session to a file and getting it back, for `--save`, `--load`,
+// `:save` and `:load`.
+//
+// A saved session is a bc program: the function definitions as they were
+// written, then an assignment for every variable and array element. Running
+// it with any bc gets the same state back. The numbers in it are decimal,
+// `ibase` is set to ten at the top and to what it was at the very end. The
+// first line says whether the math library was loaded, bc code can't do that
+// itself.
+
+use crate::mathlib;
+use crate::options::Extensions;
+use crate::Session;
+use std::fs;
+
+const HEADER: &str = "/* bc-r session */";
+const MATHLIB_HEADER: &str = "/* bc-r session, with the math library (bc -l) */";
+
+// These are written last, so the values before them are read in base ten.
+const SPECIAL_VARS: [&str; 3] = ["scale", "obase", "ibase"];
+
+impl Session {
+    // Returns whether it could be written.
+    pub fn save(&self, path: &str) -> bool {
+        match fs::write(path, self.dump()) {
+            Ok(()) => true,
+            Err(error) => {
+                eprintln!("Couldn't write `{}`: {}", path, error);
+                false
+            }
+        }
+    }
+
+    // Works on any bc file too, it's run like one. Returns whether there
+    // weren't any errors.
+    pub fn load(&mut self, path: &str) -> bool {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) => {
+                eprintln!("Couldn't read `{}`: {}", path, error);
+                return false;
+            }
+        };
+
+        // Before the functions in the file, which may redefine some of these.
+        if text.starts_with(MATHLIB_HEADER) && !self.mathlib {
+            mathlib::load(&mut self.functions);
+            self.mathlib = true;
+        }
+
+        // A saved session has long names in it even if it was made with -s.
+        let extensions = std::mem::replace(&mut self.extensions, Extensions::Allowed);
+        let ok = self.run_and_report(&text, Some(path), 1);
+        self.extensions = extensions;
+        ok
+    }
+
+    fn dump(&self) -> String {
+        let header = if self.mathlib { MATHLIB_HEADER } else { HEADER };
+        let mut text = format!("{}\nibase = A\n", header);
+
+        let mut names: Vec<&String> = self.functions.keys().collect();
+        names.sort();
+        for name in names {
+            // The math library has no text, the header brings it back.
+            if let Some(definition) = &self.functions[name].text {
+                text.push_str(&format!("{}\n", definition));
+            }
+        }
+
+        let mut names: Vec<&String> = self.env.vars.keys().collect();
+        names.sort();
+        for name in names {
+            if !SPECIAL_VARS.contains(&name.as_str()) {
+                text.push_str(&format!("{} = {}\n", name, self.env.vars[name]));
+            }
+        }
+
+        // Elements that were never set are zero anyway.
+        let mut names: Vec<&String> = self.env.arrays.keys().collect();
+        names.sort();
+        for name in names {
+            for (idx, val) in &self.env.arrays[name] {
+                if !val.is_zero() {
+                    text.push_str(&format!("{}[{}] = {}\n", name, idx, val));
+                }
+            }
+        }
+
+        for name in SPECIAL_VARS {
+            if let Some(val) = self.env.vars.get(name) {
+                text.push_str(&format!("{} = {}\n", name, val));
+            }
+        }
+        text
+    }
+}
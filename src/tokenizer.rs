@@ -1,6 +1,34 @@
+use crate::error::Error;
+
+// Where something is in the source text. `start` and `end` are byte offsets,
+// `line` and `column` (both starting from 1) are where `start` is.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    // From the start of this span to the end of the other one.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end.max(self.end),
+            ..self
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
-pub enum Token<'source> {
-    // The `usize`s used to be for line numbers, but I will not think about error handling just yet..
+pub struct Token<'source> {
+    pub kind: TokenKind<'source>,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum TokenKind<'source> {
     LeftParen,
     RightParen,
     Plus,
@@ -15,53 +43,192 @@ pub enum Token<'source> {
     MinusEquals,
     StarEquals,
     SlashEquals,
+    PercentEquals,
+    PowerEquals,
+    Increment,
+    Decrement,
+    Less,
+    LessEquals,
+    Greater,
+    GreaterEquals,
+    EqualsEquals,
+    NotEquals,
+    And,
+    Or,
+    Not,
     ArgSeperator,
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    Define,
+    Return,
+    If,
+    Else,
+    While,
+    For,
+    Break,
+    Continue,
+    Print,
+    Auto,
+    Halt,
+    Quit,
+    Str(&'source str), // Without the quotes.
     FnCall(&'source str),
-    Number(&'source str, usize),
-    Identifier(&'source str, usize),
+    Number(&'source str),
+    Identifier(&'source str),
     EOF,
 }
 
-pub fn tokens_from_text(source: &str) -> Vec<Token> {
-    let mut tk = Tokenizer::new(source);
+// Every word `parse_identifier` turns into a keyword, for the REPL's completion.
+pub const KEYWORDS: [&str; 12] = [
+    "define", "return", "if", "else", "while", "for", "break", "continue", "print", "auto", "halt",
+    "quit",
+];
+
+// The tokens of everything that could be read, and what couldn't. A character
+// bc doesn't know is skipped, an unterminated string or comment ends the tokens.
+pub fn tokens_from_text(source: &str) -> (Vec<Token<'_>>, Vec<Error>) {
+    tokens_from_line(source, 1)
+}
+
+// For a piece of a longer input, which starts on its line `first_line`.
+pub fn tokens_from_line(source: &str, first_line: usize) -> (Vec<Token<'_>>, Vec<Error>) {
+    let mut tk = Tokenizer::new(source, first_line);
     tk.tokenize();
-    tk.tokens
+    (tk.tokens, tk.errors)
 }
 
 pub struct Tokenizer<'source> {
     source_text: &'source str,
     chars: Vec<char>,
+    byte_offsets: Vec<usize>, // Where each char starts in `source_text`, and one past the end.
     current_idx: usize,
     line_num: usize,
+    line_start: usize, // Index of the first char of the current line.
+    brace_depth: usize,
+    paren_depth: usize, // Brackets too.
+    line_boundary: usize, // How many tokens there were when the last line outside braces ended.
     tokens: Vec<Token<'source>>,
+    errors: Vec<Error>,
 }
 
 impl<'source> Tokenizer<'source> {
-    fn new(source_text: &'source str) -> Self {
+    fn new(source_text: &'source str, first_line: usize) -> Self {
+        let mut byte_offsets: Vec<usize> = source_text.char_indices().map(|(idx, _)| idx).collect();
+        byte_offsets.push(source_text.len());
+
         Self {
             source_text,
             chars: source_text.chars().collect(),
+            byte_offsets,
             current_idx: 0,
-            line_num: 1,
+            line_num: first_line,
+            line_start: 0,
+            brace_depth: 0,
+            paren_depth: 0,
+            line_boundary: 0,
             tokens: vec![],
+            errors: vec![],
         }
     }
 
-    fn parse_number(&mut self) -> Token<'source> {
-        let start = self.current_idx - 1;
+    fn text(&self, start_idx: usize, end_idx: usize) -> &'source str {
+        &self.source_text[self.byte_offsets[start_idx]..self.byte_offsets[end_idx]]
+    }
+
+    // The span from the char at `start_idx` to the current char.
+    fn span_from(&self, start_idx: usize) -> Span {
+        let start = self.byte_offsets[start_idx];
+        Span {
+            start,
+            end: self.byte_offsets[self.current_idx],
+            line: self.line_num,
+            column: start_idx - self.line_start + 1,
+        }
+    }
+
+    // Numbers can go on for lines too, broken with backslashes.
+    fn parse_number(&mut self, start_idx: usize) -> Token<'source> {
+        let first_line = self.span_from(start_idx);
+        let mut seen_dot = self.chars[start_idx] == '.';
 
         while let Some(ch) = self.chars.get(self.current_idx) {
-            if is_digit(*ch) || *ch == '.' {
+            if is_digit(*ch) {
+                self.current_idx += 1;
+            } else if *ch == '.' && !seen_dot {
+                seen_dot = true;
                 self.current_idx += 1;
+            } else if *ch == '\\' && self.chars.get(self.current_idx + 1) == Some(&'\n') {
+                // bc breaks long numbers like this when it prints them, so
+                // they can be read back.
+                self.current_idx += 2;
+                self.line_num += 1;
+                self.line_start = self.current_idx;
             } else {
                 break;
             }
         }
 
-        Token::Number(&self.source_text[start..self.current_idx], self.line_num)
+        Token {
+            kind: TokenKind::Number(self.text(start_idx, self.current_idx)),
+            span: Span {
+                end: self.byte_offsets[self.current_idx],
+                ..first_line
+            },
+        }
     }
 
-    fn parse_identifier(&mut self) -> Token<'source> {
+    // Strings can go on for lines, their span starts on the first one.
+    fn parse_string(&mut self, start_idx: usize) -> Result<Token<'source>, Error> {
+        let first_line = self.span_from(start_idx);
+
+        while let Some(ch) = self.chars.get(self.current_idx) {
+            self.current_idx += 1;
+            match ch {
+                '"' => {
+                    let text = self.text(start_idx + 1, self.current_idx - 1);
+                    return Ok(Token {
+                        kind: TokenKind::Str(text),
+                        span: first_line.to(self.span_from(self.current_idx - 1)),
+                    });
+                }
+                '\n' => {
+                    self.line_num += 1;
+                    self.line_start = self.current_idx;
+                }
+                _ => {}
+            }
+        }
+
+        let msg = String::from("Unterminated string, missing the closing `\"`");
+        Err(Error::Unterminated(msg, first_line))
+    }
+
+    // A comment counts as a space, even the newlines in it don't end anything.
+    fn skip_block_comment(&mut self, start_idx: usize) -> Result<(), Error> {
+        let start = self.span_from(start_idx);
+
+        while let Some(ch) = self.chars.get(self.current_idx) {
+            self.current_idx += 1;
+            match ch {
+                '*' if self.chars.get(self.current_idx) == Some(&'/') => {
+                    self.current_idx += 1;
+                    return Ok(());
+                }
+                '\n' => {
+                    self.line_num += 1;
+                    self.line_start = self.current_idx;
+                }
+                _ => {}
+            }
+        }
+
+        let msg = String::from("Unterminated comment, missing the closing `*/`");
+        Err(Error::Unterminated(msg, start))
+    }
+
+    fn parse_identifier(&mut self) -> TokenKind<'source> {
         let start = self.current_idx - 1;
 
         while let Some(ch) = self.chars.get(self.current_idx) {
@@ -71,10 +238,22 @@ impl<'source> Tokenizer<'source> {
                 break;
             }
         }
-        let text = &self.source_text[start..self.current_idx];
+        let text = self.text(start, self.current_idx);
 
         match text {
-            _ => Token::Identifier(text, self.line_num),
+            "define" => TokenKind::Define,
+            "return" => TokenKind::Return,
+            "if" => TokenKind::If,
+            "else" => TokenKind::Else,
+            "while" => TokenKind::While,
+            "for" => TokenKind::For,
+            "break" => TokenKind::Break,
+            "continue" => TokenKind::Continue,
+            "print" => TokenKind::Print,
+            "auto" => TokenKind::Auto,
+            "halt" => TokenKind::Halt,
+            "quit" => TokenKind::Quit,
+            _ => TokenKind::Identifier(text),
         }
     }
 
@@ -86,7 +265,8 @@ impl<'source> Tokenizer<'source> {
             }
             let ch = ch.unwrap();
 
-            let token: Token;
+            let token: TokenKind;
+            let start_idx = self.current_idx;
 
             self.current_idx += 1;
             match ch {
@@ -94,80 +274,270 @@ impl<'source> Tokenizer<'source> {
                     continue;
                 }
                 '\n' => {
+                    let span = self.span_from(start_idx);
                     self.line_num += 1;
-                    token = Token::StatementEnd;
+                    self.line_start = self.current_idx;
 
-                    if let Some(tok) = self.tokens.last() {
-                        if *tok == Token::StatementEnd {
-                            continue;
-                        }
+                    // A line that ends with an operator goes on on the next one,
+                    // and so does one with a parenthesis left open. This isn't
+                    // in bc, where the newline always ends the statement and
+                    // these would be syntax errors. It's on for files and pipes
+                    // too, so they run the same as what's typed in the REPL.
+                    if self.paren_depth > 0
+                        || self.tokens.last().is_some_and(|tok| continues_line(tok.kind))
+                    {
+                        continue;
+                    }
+                    if self.tokens.last().is_none_or(|tok| tok.kind != TokenKind::StatementEnd) {
+                        self.tokens.push(Token {
+                            kind: TokenKind::StatementEnd,
+                            span,
+                        });
                     }
+                    if self.brace_depth == 0 {
+                        self.line_boundary = self.tokens.len();
+                    }
+                    continue;
                 }
-                'a'..='z' | 'A'..='Z' | '_' => {
+                // Like in bc, a backslash hides the newline after it.
+                '\\' if self.chars.get(self.current_idx) == Some(&'\n') => {
+                    self.current_idx += 1;
+                    self.line_num += 1;
+                    self.line_start = self.current_idx;
+                    continue;
+                }
+                '"' => match self.parse_string(start_idx) {
+                    Ok(string) => {
+                        self.tokens.push(string);
+                        continue;
+                    }
+                    Err(error) => {
+                        self.errors.push(error);
+                        break;
+                    }
+                },
+                'a'..='z' | '_' => {
                     token = self.parse_identifier();
                 }
-                '0'..='9' => {
-                    token = self.parse_number();
+                // Uppercase letters are digits, for `ibase`s bigger than ten.
+                '0'..='9' | 'A'..='Z' => {
+                    let number = self.parse_number(start_idx);
+                    self.tokens.push(number);
+                    continue;
+                }
+                '.' if self.chars.get(self.current_idx).is_some_and(|ch| is_digit(*ch)) => {
+                    let number = self.parse_number(start_idx);
+                    self.tokens.push(number);
+                    continue;
                 }
                 '+' => {
                     if let Some('=') = self.chars.get(self.current_idx) {
-                        token = Token::PlusEquals;
+                        token = TokenKind::PlusEquals;
+                        self.current_idx += 1;
+                    } else if let Some('+') = self.chars.get(self.current_idx) {
+                        token = TokenKind::Increment;
                         self.current_idx += 1;
                     } else {
-                        token = Token::Plus;
+                        token = TokenKind::Plus;
                     }
                 }
                 '-' => {
                     if let Some('=') = self.chars.get(self.current_idx) {
-                        token = Token::MinusEquals;
+                        token = TokenKind::MinusEquals;
+                        self.current_idx += 1;
+                    } else if let Some('-') = self.chars.get(self.current_idx) {
+                        token = TokenKind::Decrement;
                         self.current_idx += 1;
                     } else {
-                        token = Token::Minus;
+                        token = TokenKind::Minus;
                     }
                 }
                 '*' => {
                     if let Some('=') = self.chars.get(self.current_idx) {
-                        token = Token::StarEquals;
+                        token = TokenKind::StarEquals;
                         self.current_idx += 1;
                     } else {
-                        token = Token::Star;
+                        token = TokenKind::Star;
+                    }
+                }
+                '/' if self.chars.get(self.current_idx) == Some(&'*') => {
+                    self.current_idx += 1;
+                    if let Err(error) = self.skip_block_comment(start_idx) {
+                        self.errors.push(error);
+                        break;
                     }
+                    continue;
+                }
+                '#' => {
+                    // Up to the newline, which still ends the statement.
+                    while !matches!(self.chars.get(self.current_idx), None | Some('\n')) {
+                        self.current_idx += 1;
+                    }
+                    continue;
                 }
                 '/' => {
                     if let Some('=') = self.chars.get(self.current_idx) {
-                        token = Token::SlashEquals;
+                        token = TokenKind::SlashEquals;
+                        self.current_idx += 1;
+                    } else {
+                        token = TokenKind::Slash;
+                    }
+                }
+                '%' => {
+                    if let Some('=') = self.chars.get(self.current_idx) {
+                        token = TokenKind::PercentEquals;
+                        self.current_idx += 1;
+                    } else {
+                        token = TokenKind::Percent;
+                    }
+                }
+                '(' => {
+                    token = TokenKind::LeftParen;
+                    self.paren_depth += 1;
+                }
+                ')' => {
+                    token = TokenKind::RightParen;
+                    self.paren_depth = self.paren_depth.saturating_sub(1);
+                }
+                '{' => {
+                    token = TokenKind::LeftBrace;
+                    self.brace_depth += 1;
+                }
+                '}' => {
+                    token = TokenKind::RightBrace;
+                    self.brace_depth = self.brace_depth.saturating_sub(1);
+                }
+                '[' => {
+                    token = TokenKind::LeftBracket;
+                    self.paren_depth += 1;
+                }
+                ']' => {
+                    token = TokenKind::RightBracket;
+                    self.paren_depth = self.paren_depth.saturating_sub(1);
+                }
+                '=' => {
+                    if let Some('=') = self.chars.get(self.current_idx) {
+                        token = TokenKind::EqualsEquals;
                         self.current_idx += 1;
                     } else {
-                        token = Token::Slash;
+                        token = TokenKind::Equals;
+                    }
+                }
+                '<' => {
+                    if let Some('=') = self.chars.get(self.current_idx) {
+                        token = TokenKind::LessEquals;
+                        self.current_idx += 1;
+                    } else {
+                        token = TokenKind::Less;
+                    }
+                }
+                '>' => {
+                    if let Some('=') = self.chars.get(self.current_idx) {
+                        token = TokenKind::GreaterEquals;
+                        self.current_idx += 1;
+                    } else {
+                        token = TokenKind::Greater;
+                    }
+                }
+                '!' => {
+                    if let Some('=') = self.chars.get(self.current_idx) {
+                        token = TokenKind::NotEquals;
+                        self.current_idx += 1;
+                    } else {
+                        token = TokenKind::Not;
+                    }
+                }
+                '&' if self.chars.get(self.current_idx) == Some(&'&') => {
+                    token = TokenKind::And;
+                    self.current_idx += 1;
+                }
+                '|' if self.chars.get(self.current_idx) == Some(&'|') => {
+                    token = TokenKind::Or;
+                    self.current_idx += 1;
+                }
+                ';' => token = TokenKind::StatementEnd,
+                ',' => token = TokenKind::ArgSeperator,
+                '^' => {
+                    if let Some('=') = self.chars.get(self.current_idx) {
+                        token = TokenKind::PowerEquals;
+                        self.current_idx += 1;
+                    } else {
+                        token = TokenKind::Power;
                     }
                 }
-                '%' => token = Token::Percent,
-                '(' => token = Token::LeftParen,
-                ')' => token = Token::RightParen,
-                '=' => token = Token::Equals,
-                ';' => token = Token::StatementEnd,
-                ',' => token = Token::ArgSeperator,
-                '^' => token = Token::Power,
                 _ => {
-                    panic!("Weird char.");
+                    let msg = format!("Unexpected character `{}`", ch);
+                    self.errors.push(Error::Lexical(msg, self.span_from(start_idx)));
+                    continue;
                 }
             }
-            self.tokens.push(token);
+
+            // `quit` quits as soon as it's read, like in bc, even inside a
+            // function body or an `if` that's never taken. Nothing on its line
+            // runs, the lines before it do.
+            if token == TokenKind::Quit {
+                self.tokens.truncate(self.line_boundary);
+                self.tokens.push(Token {
+                    kind: token,
+                    span: self.span_from(start_idx),
+                });
+                break;
+            }
+            self.tokens.push(Token {
+                kind: token,
+                span: self.span_from(start_idx),
+            });
         }
-        self.tokens.push(Token::EOF);
+        self.tokens.push(Token {
+            kind: TokenKind::EOF,
+            span: self.span_from(self.current_idx),
+        });
     }
 }
 
-fn is_alphanumeric(ch: char) -> bool {
+// Tokens that can't end a statement, the newline after them doesn't either.
+fn continues_line(kind: TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Plus
+            | TokenKind::Minus
+            | TokenKind::Star
+            | TokenKind::Slash
+            | TokenKind::Power
+            | TokenKind::Percent
+            | TokenKind::Equals
+            | TokenKind::PlusEquals
+            | TokenKind::MinusEquals
+            | TokenKind::StarEquals
+            | TokenKind::SlashEquals
+            | TokenKind::PercentEquals
+            | TokenKind::PowerEquals
+            | TokenKind::Less
+            | TokenKind::LessEquals
+            | TokenKind::Greater
+            | TokenKind::GreaterEquals
+            | TokenKind::EqualsEquals
+            | TokenKind::NotEquals
+            | TokenKind::And
+            | TokenKind::Or
+            | TokenKind::Not
+            | TokenKind::ArgSeperator
+    )
+}
+
+// What a name can have after its first letter. The REPL's completion uses it too.
+#[allow(clippy::match_like_matches_macro)]
+pub fn is_alphanumeric(ch: char) -> bool {
     match ch {
         '0'..='9' | 'a'..='z' | 'A'..='Z' | '_' => true,
         _ => false,
     }
 }
 
+#[allow(clippy::match_like_matches_macro)]
 fn is_digit(ch: char) -> bool {
     match ch {
-        '0'..='9' => true,
+        '0'..='9' | 'A'..='Z' => true,
         _ => false,
     }
 }
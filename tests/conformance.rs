@@ -0,0 +1,441 @@
+// Programs and what GNU bc prints for them. The binary is run the way a
+// user would run it, with the program on its standard input.
+
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn bc(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bc"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("couldn't start bc");
+
+    // bc may stop before it reads all of it, at a `quit` or with -e.
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn check(args: &[&str], cases: &[(&str, &str)]) {
+    for (program, expected) in cases {
+        let output = bc(args, &format!("{}\n", program));
+        assert_eq!(stdout(&output), format!("{}\n", expected), "for the program: {}", program);
+    }
+}
+
+#[test]
+fn precedence() {
+    check(
+        &[],
+        &[
+            ("1+2*3", "7"),
+            ("(1+2)*3", "9"),
+            ("2*3^2", "18"),
+            ("1+7%3", "2"),
+            ("8-3%2", "7"),
+            ("2*7%3", "2"),
+            ("7%3*2", "2"),
+            ("-2^2", "4"),
+            ("-3^3", "-27"),
+            ("-2*-3", "6"),
+            ("2^-1", "0"),
+            ("1+1==2", "1"),
+            ("2<=1", "0"),
+            ("1!=1", "0"),
+            ("!0", "1"),
+            ("!1+1", "0"),
+            ("1 || 0 && 0", "1"),
+            ("0 && 1 || 0", "0"),
+            ("x=1<2; x", "1\n1"),
+        ],
+    );
+}
+
+#[test]
+fn associativity() {
+    check(
+        &[],
+        &[
+            ("10-4-3", "3"),
+            ("100/10/5", "2"),
+            ("2^3^2", "512"),
+            ("2^2^0", "2"),
+            ("x=y=3; x+y", "6"),
+            ("x=2; y=3; x+=y*=2; x; y", "8\n6"),
+        ],
+    );
+}
+
+#[test]
+fn assignment() {
+    check(
+        &[],
+        &[
+            ("x=2; x^=3; x", "8"),
+            ("x=10; x-=2*3; x", "4"),
+            ("x=7; x%=4; x", "3"),
+            ("a[2]=5; a[2]*=3; a[2]", "15"),
+            ("x=5; x++; x; --x", "5\n6\n5"),
+            ("i=0; a[i++]=4; i; a[0]", "1\n4"),
+            ("(x = 5); (x += 1); x = 2", "5\n6"),
+            ("a[16777215]=7; a[16777215]; a[100]", "7\n0"),
+        ],
+    );
+}
+
+#[test]
+fn scale() {
+    check(
+        &[],
+        &[
+            ("1/3", "0"),
+            ("scale=2; 1/3", ".33"),
+            ("scale=5; 2/3*3", "1.99998"),
+            ("scale=3; -7/2", "-3.500"),
+            ("-7/2", "-3"),
+            ("-7%2", "-1"),
+            ("scale=2; 5%3", ".02"),
+            ("1.50+1", "2.50"),
+            ("0.5", ".5"),
+            ("-0.5", "-.5"),
+            ("2.0*3.00", "6.00"),
+            ("sqrt(2)", "1"),
+            ("scale=10; sqrt(2)", "1.4142135623"),
+            ("length(123.45)", "5"),
+            ("scale(123.45)", "2"),
+            ("scale=20; sin(1)", ".84147098480789650665"),
+            ("sin(1.5)", "0"),
+            ("scale=5; cos(0)", "1.00000"),
+            ("scale=-1; scale", "0"),
+            ("scale=10^18; scale", "0"),
+            (
+                "2^300",
+                "203703597633448608626844568840937816105146839366593625063614044935438\\\n\
+                 1299763336706183397376",
+            ),
+        ],
+    );
+}
+
+#[test]
+fn bases() {
+    check(
+        &[],
+        &[
+            ("obase=16; 255", "FF"),
+            ("ibase=16; FF", "255"),
+            ("obase=2; 10", "1010"),
+            ("ibase=2; 1010", "10"),
+            ("obase=16; -255", "-FF"),
+        ],
+    );
+}
+
+#[test]
+fn functions_and_strings() {
+    check(
+        &[],
+        &[
+            (
+                "define f(n) { if (n <= 1) return 1; return n * f(n-1) }\nf(10)",
+                "3628800",
+            ),
+            (
+                "define g(n) { auto x; x = n * 2; return x }\nx = 1; g(5); x",
+                "10\n1",
+            ),
+            ("print \"a\", 1+1, \"\\n\"", "a2"),
+            ("for (i = 0; i < 3; i++) i", "0\n1\n2"),
+        ],
+    );
+}
+
+#[test]
+fn comments() {
+    check(
+        &[],
+        &[
+            ("/* one\n   two */ 1", "1"),
+            ("1 # the rest of the line", "1"),
+            ("# a whole line\n2", "2"),
+            ("1 +/* between */2", "3"),
+            ("x = 4 /* a newline in a comment\n doesn't end the statement */ + 1; x", "5"),
+        ],
+    );
+
+    // The lines in a comment still count for the errors after it.
+    let output = bc(&[], "/* one\ntwo\nthree */\n)\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("4 | )"));
+
+    let output = bc(&[], "1\n/* never closed\n2\n");
+    assert_eq!(stdout(&output), "1\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unterminated comment"));
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn math_library() {
+    check(
+        &["-l"],
+        &[
+            ("scale", "20"),
+            ("s(0)", "0"),
+            ("c(0)", "1.00000000000000000000"),
+            ("4*a(1)", "3.14159265358979323844"),
+            ("e(1)", "2.71828182845904523536"),
+            ("l(2)", ".69314718055994530941"),
+            ("s(1)", ".84147098480789650665"),
+            ("j(0,1)", ".76519768655796655144"),
+        ],
+    );
+}
+
+#[test]
+fn files_then_stdin() {
+    let dir = std::env::temp_dir();
+    let first = dir.join(format!("bc-conformance-{}-1.bc", std::process::id()));
+    let second = dir.join(format!("bc-conformance-{}-2.bc", std::process::id()));
+    fs::write(&first, "define f(x) {\n  return x * 2\n}\n").unwrap();
+    fs::write(&second, "a = f(21)\na\n").unwrap();
+
+    let output = bc(&[first.to_str().unwrap(), second.to_str().unwrap()], "a + 1\n");
+    assert_eq!(stdout(&output), "42\n43\n");
+    assert!(output.status.success());
+
+    // An error says which file it's in.
+    fs::write(&second, "a = f(21)\n)\n").unwrap();
+    let output = bc(&[first.to_str().unwrap(), second.to_str().unwrap()], "");
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    assert!(stderr.contains(&format!("--> {}, line 2", second.display())), "{}", stderr);
+    fs::remove_file(first).unwrap();
+    fs::remove_file(second).unwrap();
+}
+
+#[test]
+fn else_on_the_next_line() {
+    // A file runs a statement at a time, but the `else` still finds its `if`,
+    // the same as when the program is given with -e.
+    let program = "if (0) 2\nelse 3\nif (1) 4\n\n5\nif (0) 6\n";
+    let file = std::env::temp_dir().join(format!("bc-conformance-{}-else.bc", std::process::id()));
+    fs::write(&file, program).unwrap();
+    let output = bc(&[file.to_str().unwrap()], "");
+    fs::remove_file(file).unwrap();
+    assert_eq!(stdout(&output), "3\n4\n5\n");
+    assert!(output.status.success());
+
+    let output = bc(&["-e", program], "");
+    assert_eq!(stdout(&output), "3\n4\n5\n");
+    let output = bc(&[], program);
+    assert_eq!(stdout(&output), "3\n4\n5\n");
+}
+
+#[test]
+fn exit_status() {
+    let output = bc(&[], "2+2\n");
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+
+    // The statements after an error still run.
+    let output = bc(&[], "1/0\n2+3\n");
+    assert_eq!(stdout(&output), "5\n");
+    assert!(!output.status.success());
+    // And the error points at the statement that failed.
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 | 1/0"));
+
+    // Each statement runs as it's read, the errors say where in the whole
+    // input they are.
+    let output = bc(&[], "1\n)\n2\n");
+    assert_eq!(stdout(&output), "1\n2\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("2 | )"));
+
+    // So do the ones around a syntax error, and every error is reported.
+    let output = bc(&[], "1; )\n2 @ 3\nx = 4 $\nx + 5\n");
+    assert_eq!(stdout(&output), "1\n5\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr).matches("error").count(), 4);
+
+    let output = bc(&[], "(1+\n");
+    assert_eq!(output.status.code(), Some(1));
+
+    // The special variables can't be local, their values would go unchecked.
+    let output = bc(&[], "define f(obase) { return 12 }\nf(1)\n");
+    assert_eq!(stdout(&output), "");
+    assert_eq!(output.status.code(), Some(1));
+
+    // Recursion that never ends is an error, not a crash.
+    let output = bc(&[], "define f(n) { return f(n+1) }\nf(0)\n2\n");
+    assert_eq!(stdout(&output), "2\n");
+    assert_eq!(output.status.code(), Some(1));
+
+    // So is nesting too deep to parse.
+    let nested = format!("{}1{}\n2\n", "(".repeat(200000), ")".repeat(200000));
+    let output = bc(&[], &nested);
+    assert_eq!(stdout(&output), "2\n");
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = bc(&["/this/file/does/not/exist.bc"], "");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn command_line() {
+    // -e and -f run in order, and then there's nothing to read.
+    let output = bc(&["-e", "x=3", "--expression=x*2", "-e", "x+1"], "100\n");
+    assert_eq!(stdout(&output), "6\n4\n");
+
+    let output = bc(&["-lq"], "scale\n");
+    assert_eq!(stdout(&output), "20\n");
+
+    let output = bc(&["-s"], "if (1) 2 else 3\n");
+    assert_eq!(stdout(&output), "");
+    assert!(!output.status.success());
+
+    // Comparisons only go in conditions, and a returned value in parentheses.
+    let output = bc(&["-s"], "x = 1 < 2\ndefine f(x) { return x }\nfor (i = 0; i < 2; i++) i\n");
+    assert_eq!(stdout(&output), "0\n1\n");
+    assert_eq!(String::from_utf8_lossy(&output.stderr).matches("POSIX").count(), 2);
+
+    let output = bc(&["--warn"], "if (1) 2 else 3\n");
+    assert_eq!(stdout(&output), "2\n");
+    assert!(!output.stderr.is_empty());
+    assert!(output.status.success());
+
+    // Only a backslash carries a POSIX statement on to the next line.
+    for input in ["1 +\n2\n", "sqrt(4\n)\n"] {
+        let output = bc(&["-s"], input);
+        assert_eq!(stdout(&output), "");
+        assert!(!output.status.success());
+    }
+    let output = bc(&["-s"], "1 + \\\n2\n1 /* a\nb */ + 2\n");
+    assert_eq!(stdout(&output), "3\n3\n");
+    assert!(output.status.success());
+
+    let output = bc(&["--warn"], "1 +\n2\n");
+    assert_eq!(stdout(&output), "3\n");
+    assert!(!output.stderr.is_empty());
+
+    let output = bc(&["--bogus"], "");
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_bc"))
+        .args(["-e", "e(1)"])
+        .env("BC_ENV_ARGS", "-l")
+        .output()
+        .unwrap();
+    assert_eq!(stdout(&output), "2.71828182845904523536\n");
+}
+
+#[test]
+fn quit_and_halt() {
+    // Only the keyword quits, not a name that starts with it.
+    let output = bc(&[], "quitting = 3\nquitting\n");
+    assert_eq!(stdout(&output), "3\n");
+
+    // `quit` is seen when it's read, the rest of its line doesn't run.
+    let output = bc(&[], "1\n2; quit\n3\n");
+    assert_eq!(stdout(&output), "1\n");
+    assert!(output.status.success());
+
+    let output = bc(&[], "if (0) quit\n3\n");
+    assert_eq!(stdout(&output), "");
+
+    // `halt` only stops bc when it runs.
+    check(
+        &[],
+        &[
+            ("if (0) halt\n3", "3"),
+            ("if (1) { 2; halt; 3 }\n4", "2"),
+            ("define f() { halt }\n1; f(); 2", "1"),
+        ],
+    );
+
+    // Nothing after a `quit` runs, not even the next source.
+    let output = bc(&["-e", "x=5", "-e", "quit", "-e", "x"], "");
+    assert_eq!(stdout(&output), "");
+    assert!(output.status.success());
+}
+
+#[test]
+fn line_continuation() {
+    check(
+        &[],
+        &[
+            ("1 +\n2", "3"),
+            ("1 + \\\n2", "3"),
+            ("12\\\n34", "1234"),
+            ("x = (1 +\n2) *\n3; x", "9"),
+            ("define f(a,\nb) { return a - b }\nf(5,\n2)", "3"),
+            ("1 &&\n0", "0"),
+            ("(1\n+2)", "3"),
+            ("sqrt(4\n)", "2"),
+            ("a[1\n] = 3; a[1]", "3"),
+        ],
+    );
+}
+
+#[test]
+fn repl_commands() {
+    // -i runs the REPL even on a pipe. It keeps its history in the home directory.
+    let home = std::env::temp_dir().join(format!("bc-conformance-{}-home", std::process::id()));
+    fs::create_dir_all(&home).unwrap();
+    let saved = home.join("session.bc");
+    let repl = |input: &str| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_bc"))
+            .arg("-iq")
+            .env("HOME", &home)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+        child.wait_with_output().unwrap()
+    };
+
+    let output = repl("x = 2; a[1] = 3\n:vars\n:ast 1+2*3\n");
+    assert_eq!(
+        stdout(&output),
+        "ibase = 10\nobase = 10\nscale = 0\nx = 2\na[1] = 3\n(Plus 1 (Star 2 3))\n\n"
+    );
+
+    let program = format!(
+        "define f(n) {{ return n * 3 }}\nx = 1.5; obase = 16\n:save {}\n",
+        saved.display()
+    );
+    repl(&program);
+    let output = repl(&format!(":load {}\nf(x)\n", saved.display()));
+    assert_eq!(stdout(&output), "4.8\n\n");
+
+    fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn saved_sessions() {
+    let path = std::env::temp_dir().join(format!("bc-conformance-{}-saved.bc", std::process::id()));
+    let save = format!("--save={}", path.display());
+    let load = format!("--load={}", path.display());
+
+    let program = "scale = 5\ndefine f(x) { return s(x) + x }\nx = 1.50\na[3] = -2\nibase = 16\n";
+    let output = bc(&["-l", &save], program);
+    assert_eq!(stdout(&output), "");
+    assert!(output.status.success());
+
+    // The math library comes back too, without -l.
+    let output = bc(&[&load], "x\na[3]\nf(0)\nscale\nibase\nA\n");
+    assert_eq!(stdout(&output), "1.50\n-2\n0\n5\n16\n10\n");
+
+    // It's a bc program, even with -s nothing in it is an error.
+    let output = bc(&["-s", &load], "x\n");
+    assert_eq!(stdout(&output), "1.50\n");
+    assert!(output.status.success());
+
+    fs::remove_file(&path).unwrap();
+    let output = bc(&[&load], "1\n");
+    assert!(!output.status.success());
+}